@@ -1,12 +1,396 @@
+use crate::chip8::clock::Chip8Speed;
+use crate::chip8::config::{Chip8Config, Chip8Timing};
+use crate::chip8::octo_cartridge::Chip8OctoOptions;
+use crate::chip8::rom_loader::{self, Chip8Rom, Chip8RomFormat};
+use crate::chip8::stats::Chip8RateCounter;
 use crate::chip8::Chip8;
+use crate::graphics::GraphicsOutput;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::renderer::RenderMode;
+use crate::rom_browser::{RecentRoms, RomBrowser};
+use crate::rom_database::{RomDatabase, RomInfo};
+use crate::screenshot;
+use crate::settings::{RomSettings, Settings};
+use crate::view_layout::ViewLayout;
+
+use crossterm::event::KeyCode;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub struct App {
     pub sys: Chip8,
+    pub rom: String,
+    // The ROM as it was read when loaded, resets run it again without reading the file
+    pub rom_program: Vec<u8>,
+    pub status: String,
+    pub save_slot: u8,
+    pub movie_path: PathBuf,
+    pub palette: Palette,
+    pub screenshot_scale: usize,
+    pub gif_path: Option<PathBuf>,
+    pub settings: Settings,
+    pub database: RomDatabase,
+    pub rom_hash: String,
+    pub rom_title: Option<String>,
+    pub rom_info: Option<RomInfo>,
+    pub tick_rate: Duration,
+    pub key_timeout: Duration,
+    pub key_bindings: HashMap<KeyCode, u8>,
+    pub browser: Option<RomBrowser>,
+    pub recent: RecentRoms,
+    pub render_mode: RenderMode,
+    // Palette picked on the command line or with the hotkey, wins over the settings
+    pub palette_override: Option<Palette>,
+    // Timing from the command line, wins over the settings
+    pub timing_override: Option<Chip8Timing>,
+    pub phosphor: Phosphor,
+    pub graphics: GraphicsOutput,
+    pub layout: ViewLayout,
+    // Frames drawn and time the ui spends building them, next to the clock's stats
+    pub frames: Chip8RateCounter,
+    pub ui_busy: Chip8RateCounter,
 }
 
 impl App {
-    pub fn new(filename: &str) -> App {
-        let c8 = Chip8::new(filename);
-        App { sys: c8 }
+    const SAVE_DIR: &'static str = "saves";
+    const MOVIE_DIR: &'static str = "movies";
+    const SCREENSHOT_DIR: &'static str = "screenshots";
+    const GIF_DIR: &'static str = "gifs";
+    const SETTINGS_FILE: &'static str = "chip8.toml";
+    const DEFAULT_TICK_RATE: Duration = Duration::from_millis(16);
+    const DEFAULT_KEY_TIMEOUT: Duration = Duration::from_millis(250);
+
+    pub fn new(filename: &str, format: Option<Chip8RomFormat>) -> io::Result<App> {
+        let rom = rom_loader::load(filename, format)?;
+        let c8 = Chip8::with_program(filename, &rom.program);
+        let (settings, status) = match Settings::load_file(Path::new(App::SETTINGS_FILE)) {
+            Ok(settings) => (settings, String::new()),
+            Err(err) => (
+                Settings::new(),
+                format!("Ignoring {}: {}", App::SETTINGS_FILE, err),
+            ),
+        };
+        let mut database = RomDatabase::new();
+        let status = match database.load_file(Path::new(RomDatabase::LOCAL_FILE)) {
+            Ok(_) => status,
+            Err(err) => format!("Ignoring {}: {}", RomDatabase::LOCAL_FILE, err),
+        };
+        let mut app = App {
+            sys: c8,
+            rom: filename.to_string(),
+            rom_program: Vec::new(),
+            status,
+            save_slot: 0,
+            movie_path: Path::new(App::MOVIE_DIR).join(format!("{}.c8m", App::rom_name(filename))),
+            palette: Palette::new(),
+            screenshot_scale: 8,
+            gif_path: None,
+            settings,
+            database,
+            rom_hash: String::new(),
+            rom_title: None,
+            rom_info: None,
+            tick_rate: App::DEFAULT_TICK_RATE,
+            key_timeout: App::DEFAULT_KEY_TIMEOUT,
+            key_bindings: HashMap::new(),
+            browser: None,
+            recent: RecentRoms::file()
+                .map(|file| RecentRoms::load_file(&file))
+                .unwrap_or_default(),
+            render_mode: RenderMode::Braille,
+            palette_override: None,
+            timing_override: None,
+            phosphor: Phosphor::new(),
+            graphics: GraphicsOutput::new(),
+            layout: ViewLayout::load_file(Path::new(ViewLayout::FILE)),
+            frames: Chip8RateCounter::new(),
+            ui_busy: Chip8RateCounter::new(),
+        };
+        app.apply_settings(rom);
+        Ok(app)
+    }
+
+    //Reset the machine with another ROM and switch to its settings
+    pub fn load_rom(&mut self, filename: &str) {
+        //A ROM that can't be read leaves the machine as it was
+        let rom = match rom_loader::load(filename, None) {
+            Ok(rom) => rom,
+            Err(err) => {
+                self.status = format!("Cannot load {}: {}", filename, err);
+                return;
+            }
+        };
+        self.sys.load_program_reset(filename, &rom.program);
+        self.rom = filename.to_string();
+        self.movie_path =
+            Path::new(App::MOVIE_DIR).join(format!("{}.c8m", App::rom_name(filename)));
+        self.apply_settings(rom);
+        self.status = format!("Loaded {}", filename);
+        self.add_recent();
+    }
+
+    //Only ROMs loaded in the interactive emulator count as recent, headless and tool runs leave the list alone
+    pub fn add_recent(&mut self) {
+        self.recent.add(&self.rom);
+        let res = match RecentRoms::file() {
+            Some(file) => self.recent.save_file(&file),
+            None => Ok(()),
+        };
+        if let Err(err) = res {
+            self.status = format!("Saving recent ROMs failed: {}", err);
+        }
+    }
+
+    //Starts in the directory of the current ROM
+    pub fn open_browser(&mut self) {
+        let dir = Path::new(&self.rom)
+            .parent()
+            .filter(|dir| dir.is_dir())
+            .unwrap_or_else(|| Path::new("."));
+        self.browser = Some(RomBrowser::new(dir, &self.recent));
+    }
+
+    //Keys go to the browser while it is open, Enter loads the selected ROM and Esc closes it
+    pub fn browser_key(&mut self, code: KeyCode) {
+        let browser = match self.browser.as_mut() {
+            Some(browser) => browser,
+            None => return,
+        };
+        match code {
+            KeyCode::Up => browser.previous(),
+            KeyCode::Down => browser.next(),
+            KeyCode::Backspace | KeyCode::Left => browser.parent(&self.recent),
+            KeyCode::Enter | KeyCode::Right => {
+                if let Some(path) = browser.select(&self.recent) {
+                    self.browser = None;
+                    self.load_rom(&path.to_string_lossy());
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('r') => self.browser = None,
+            _ => {}
+        }
+    }
+
+    //Speed of the cpu and the timers, the scheduler picks it up at the next frame
+    pub fn set_speed(&mut self, speed: Chip8Speed) {
+        self.sys.clock.write().unwrap().speed = speed;
+        self.status = format!("Speed {}", speed.name());
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        self.layout.fullscreen = !self.layout.fullscreen;
+        self.status = if self.layout.fullscreen {
+            "Fullscreen".to_string()
+        } else {
+            format!("Layout {}", self.layout.kind.name())
+        };
+        self.save_layout();
+    }
+
+    pub fn next_layout(&mut self) {
+        self.layout.kind = self.layout.kind.next();
+        self.layout.fullscreen = false;
+        self.status = format!("Layout {}", self.layout.kind.name());
+        self.save_layout();
+    }
+
+    //Changes the share of the screen in the current layout by delta percent
+    pub fn resize_screen(&mut self, delta: i16) {
+        self.layout.resize(delta);
+        self.status = format!(
+            "Layout {}, screen {}%",
+            self.layout.kind.name(),
+            self.layout.size()
+        );
+        self.save_layout();
+    }
+
+    fn save_layout(&mut self) {
+        if let Err(err) = self.layout.save_file(Path::new(ViewLayout::FILE)) {
+            self.status = format!("Saving {} failed: {}", ViewLayout::FILE, err);
+        }
+    }
+
+    //Global defaults from chip8.toml, then the ROM database and cartridge options, then the overrides for the current ROM (matched by SHA-1)
+    fn apply_settings(&mut self, rom: Chip8Rom) {
+        self.rom_hash = Settings::rom_hash(&rom.program);
+        self.rom_info = self.database.lookup(&self.rom_hash).cloned();
+        let cartridge = rom.cartridge;
+        self.rom_program = rom.program;
+        let known = self
+            .rom_info
+            .as_ref()
+            .map(RomInfo::to_settings)
+            .into_iter()
+            .chain(
+                cartridge
+                    .as_ref()
+                    .map(|cartridge| RomSettings::from_octo(&cartridge.options)),
+            )
+            .collect::<Vec<RomSettings>>();
+        let rom_settings = self.settings.for_rom(&self.rom_hash, &known);
+        self.rom_title = rom_settings.name.clone();
+        //Palette from the command line, then the settings, then the colours a cartridge brings
+        let named = rom_settings.palette.as_deref().and_then(|name| {
+            let palette = Palette::from_name(name);
+            if palette.is_none() {
+                self.status = format!("Unknown palette {}", name);
+            }
+            palette
+        });
+        self.palette = match self.palette_override.or(named) {
+            Some(palette) => palette,
+            None => {
+                let mut palette = Palette::new();
+                if let Some(options) = cartridge.as_ref().map(|cartridge| &cartridge.options) {
+                    let color = |color: &Option<String>| {
+                        color.as_deref().and_then(Chip8OctoOptions::parse_color)
+                    };
+                    palette.name = "cartridge";
+                    palette.on = color(&options.fill_color).unwrap_or(palette.on);
+                    palette.off = color(&options.background_color).unwrap_or(palette.off);
+                }
+                palette
+            }
+        };
+
+        let mut config = self.sys.config.write().unwrap();
+        *config = Chip8Config::new();
+        rom_settings.apply(&mut config);
+        config.timing = self.timing_override.unwrap_or(config.timing);
+        drop(config);
+        self.tick_rate = rom_settings
+            .tick_rate_ms
+            .map_or(App::DEFAULT_TICK_RATE, Duration::from_millis);
+        self.key_timeout = rom_settings
+            .key_timeout_ms
+            .map_or(App::DEFAULT_KEY_TIMEOUT, Duration::from_millis);
+        self.key_bindings = match rom_settings.key_bindings() {
+            Ok(bindings) => bindings,
+            Err(err) => {
+                self.status = err;
+                RomSettings::default().key_bindings().unwrap()
+            }
+        };
+        if self.settings.roms.contains_key(&self.rom_hash) {
+            self.status = format!(
+                "Applied settings for {}",
+                rom_settings.name.as_deref().unwrap_or(&self.rom_hash)
+            );
+        } else if let Some(info) = &self.rom_info {
+            self.status = match &info.platform {
+                Some(platform) => format!("Recognised {} ({})", info.title, platform),
+                None => format!("Recognised {}, no supported platform", info.title),
+            };
+        }
+    }
+
+    fn rom_name(filename: &str) -> String {
+        Path::new(filename)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "rom".to_string())
+    }
+
+    //Quick save slots are kept per ROM, e.g. saves/BRIX.slot0.c8s
+    pub fn save_slot_path(&self) -> PathBuf {
+        Path::new(App::SAVE_DIR).join(format!(
+            "{}.slot{}.c8s",
+            App::rom_name(&self.rom),
+            self.save_slot
+        ))
+    }
+
+    //Screenshots are named after the ROM and frame, e.g. screenshots/BRIX-1234.png
+    pub fn screenshot(&mut self) {
+        let frame = self.sys.timers.read().unwrap().frame;
+        let base =
+            Path::new(App::SCREENSHOT_DIR).join(format!("{}-{}", App::rom_name(&self.rom), frame));
+        let display = self.sys.display.read().unwrap().clone();
+        self.status = match screenshot::save(&display, self.screenshot_scale, &self.palette, &base)
+        {
+            Ok(()) => format!("Screenshot saved to {}.png", base.display()),
+            Err(err) => format!("Screenshot failed: {}", err),
+        };
+    }
+
+    //Recording starts at the next frame and is written to gif_path (or gifs/<rom>-<frame>.gif) when stopped
+    pub fn start_gif(&mut self) {
+        if self.gif_path.is_none() {
+            let frame = self.sys.timers.read().unwrap().frame;
+            self.gif_path = Some(Path::new(App::GIF_DIR).join(format!(
+                "{}-{}.gif",
+                App::rom_name(&self.rom),
+                frame
+            )));
+        }
+        self.sys.start_gif();
+        self.status = "Recording GIF".to_string();
+    }
+
+    pub fn stop_gif(&mut self) {
+        let (gif, path) = match (self.sys.stop_gif(), self.gif_path.take()) {
+            (Some(gif), Some(path)) => (gif, path),
+            _ => return,
+        };
+        let res = gif
+            .encode(self.screenshot_scale, self.palette.planes())
+            .and_then(|data| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&path, data)
+            });
+        self.status = match res {
+            Ok(()) => format!("Saved {} frames to {}", gif.frame_count(), path.display()),
+            Err(err) => format!("Saving GIF failed: {}", err),
+        };
+    }
+
+    //Movies are always recorded from power on so they can be replayed on a fresh machine
+    pub fn start_recording(&mut self) {
+        self.sys.load_program_reset(&self.rom, &self.rom_program);
+        self.sys.start_recording();
+        self.status = format!("Recording to {}", self.movie_path.display());
+    }
+
+    pub fn stop_recording(&mut self) {
+        let movie = match self.sys.stop_recording() {
+            Some(movie) => movie,
+            None => return,
+        };
+        let res = match self.movie_path.parent() {
+            Some(dir) => fs::create_dir_all(dir),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::write(&self.movie_path, movie.to_bytes()));
+        self.status = match res {
+            Ok(()) => format!(
+                "Saved {} frames to {}",
+                movie.frames,
+                self.movie_path.display()
+            ),
+            Err(err) => format!("Saving movie failed: {}", err),
+        };
+    }
+
+    pub fn quick_save(&mut self) {
+        let path = self.save_slot_path();
+        self.status = match self.sys.save_state(&path) {
+            Ok(()) => format!("Saved slot {} to {}", self.save_slot, path.display()),
+            Err(err) => format!("Save to slot {} failed: {}", self.save_slot, err),
+        };
+    }
+
+    pub fn quick_load(&mut self) {
+        let path = self.save_slot_path();
+        self.status = match self.sys.load_state(&path) {
+            Ok(()) => format!("Loaded slot {}", self.save_slot),
+            Err(err) => format!("Load from slot {} failed: {}", self.save_slot, err),
+        };
     }
 }
@@ -1,22 +1,58 @@
+pub mod batch;
+mod breakpoints;
+pub mod clock;
+pub mod config;
 pub mod display;
+pub mod gif_recorder;
+mod instruction_decoder;
 mod keyboard;
 mod memory;
+pub mod movie;
+pub mod octo_assembler;
+pub mod octo_cartridge;
 mod registers;
+pub mod rng;
+pub mod rom_loader;
+mod save_state;
+mod sprites;
+pub mod stats;
+mod symbols;
 pub mod thread_messages;
 mod timers;
+mod vip_timing;
 
+use batch::{Chip8Batch, Chip8BatchEnd, Chip8BatchResult};
+use breakpoints::Chip8Breakpoints;
+use clock::{Chip8Clock, Chip8Speed};
+use config::{Chip8Config, Chip8Timing};
 use display::Chip8Display;
+use gif_recorder::Chip8GifRecorder;
+use instruction_decoder::Chip8InstructionDecoder;
 use keyboard::Chip8Keyboard;
 use memory::Chip8Memory;
+use movie::Chip8Movie;
 use registers::Chip8Registers;
+use rng::Chip8Rng;
+use rom_loader::Chip8RomFormat;
+use save_state::Chip8State;
+use sprites::Chip8SpriteTracker;
+use stats::Chip8Stats;
+use symbols::Chip8Symbols;
 use thread_messages::Chip8ControlMessage;
 use timers::Chip8Timers;
+use vip_timing::Chip8VipTiming;
 
 use tokio::sync::mpsc::Receiver;
 use tokio::time::{self};
 
 use std::collections::VecDeque;
-use std::{fs, sync::Arc, sync::RwLock, time::Duration};
+use std::{
+    fs, io,
+    path::Path,
+    sync::Arc,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
 
 type SharedDisplay = Arc<RwLock<Chip8Display>>;
 type SharedMemory = Arc<RwLock<Chip8Memory>>;
@@ -24,6 +60,18 @@ type SharedRegisters = Arc<RwLock<Chip8Registers>>;
 type SharedTimers = Arc<RwLock<Chip8Timers>>;
 type SharedPrevInsts = Arc<RwLock<VecDeque<String>>>;
 type SharedKeyboard = Arc<RwLock<Chip8Keyboard>>;
+type SharedSprites = Arc<RwLock<Chip8SpriteTracker>>;
+type SharedSymbols = Arc<RwLock<Chip8Symbols>>;
+type SharedBreakpoints = Arc<RwLock<Chip8Breakpoints>>;
+type SharedConfig = Arc<RwLock<Chip8Config>>;
+type SharedRng = Arc<RwLock<Chip8Rng>>;
+type SharedMovie = Arc<RwLock<Option<Chip8Movie>>>;
+type SharedGif = Arc<RwLock<Option<Chip8GifRecorder>>>;
+type SharedClock = Arc<RwLock<Chip8Clock>>;
+type SharedStats = Arc<RwLock<Chip8Stats>>;
+
+//Every part of the machine is shared so a clone is another handle to the same machine
+#[derive(Clone)]
 pub struct Chip8 {
     pub display: SharedDisplay,
     pub memory: SharedMemory,
@@ -31,12 +79,30 @@ pub struct Chip8 {
     pub timers: SharedTimers,
     pub instructions: SharedPrevInsts,
     pub keyboard: SharedKeyboard,
+    pub sprites: SharedSprites,
+    pub symbols: SharedSymbols,
+    pub breakpoints: SharedBreakpoints,
+    pub config: SharedConfig,
+    pub rng: SharedRng,
+    pub recording: SharedMovie,
+    pub playback: SharedMovie,
+    pub gif: SharedGif,
+    pub clock: SharedClock,
+    pub stats: SharedStats,
 }
 
 impl Chip8 {
     const PROGRAM_START_ADDRESS: usize = 0x200;
 
-    pub fn new(filename: &str) -> Chip8 {
+    pub fn new(filename: &str, format: Option<Chip8RomFormat>) -> io::Result<Chip8> {
+        Ok(Chip8::with_program(
+            filename,
+            &rom_loader::read(filename, format)?,
+        ))
+    }
+
+    //A machine running a program that was already read, filename is only used to find its symbols
+    pub fn with_program(filename: &str, contents: &[u8]) -> Chip8 {
         let sys = Chip8 {
             display: Arc::new(RwLock::new(Chip8Display::new())),
             memory: Arc::new(RwLock::new(Chip8Memory::new())),
@@ -44,16 +110,26 @@ impl Chip8 {
             timers: Arc::new(RwLock::new(Chip8Timers::new())),
             instructions: Arc::new(RwLock::new(VecDeque::from(vec![" ".to_string(); 10]))),
             keyboard: Arc::new(RwLock::new(Chip8Keyboard::new())),
+            sprites: Arc::new(RwLock::new(Chip8SpriteTracker::new())),
+            symbols: Arc::new(RwLock::new(Chip8Symbols::new())),
+            breakpoints: Arc::new(RwLock::new(Chip8Breakpoints::new())),
+            config: Arc::new(RwLock::new(Chip8Config::new())),
+            rng: Arc::new(RwLock::new(Chip8Rng::new())),
+            recording: Arc::new(RwLock::new(None)),
+            playback: Arc::new(RwLock::new(None)),
+            gif: Arc::new(RwLock::new(None)),
+            clock: Arc::new(RwLock::new(Chip8Clock::new())),
+            stats: Arc::new(RwLock::new(Chip8Stats::new())),
         };
-        sys.load_file(filename);
-        return sys;
+        sys.load_program(filename, contents);
+        sys
     }
 
-    fn load_file(&self, filename: &str) {
-        let contents = fs::read(filename).expect("Something went wrong reading the file");
+    //Places the program read from filename in memory
+    fn load_program(&self, filename: &str, contents: &[u8]) {
         let mut memory = self.memory.write().unwrap();
         assert!(
-            contents.len() < (memory.raw_array.len() - Chip8::PROGRAM_START_ADDRESS),
+            contents.len() <= (memory.raw_array.len() - Chip8::PROGRAM_START_ADDRESS),
             "Cannot load selected file as it is greater than program memory size"
         );
 
@@ -61,9 +137,104 @@ impl Chip8 {
         for (index, data) in contents.iter().enumerate() {
             memory.raw_array[current_address + index] = data.to_owned();
         }
+
+        //Pick up a symbol file sitting next to the ROM (e.g. roms/BRIX.sym)
+        let mut symbols = self.symbols.write().unwrap();
+        symbols.clear();
+        let symbol_file = Path::new(filename).with_extension("sym");
+        if symbol_file.is_file() {
+            let _ = symbols.load_file(&symbol_file);
+        }
+    }
+
+    pub fn set_seed(&self, seed: u64) {
+        *self.rng.write().unwrap() = Chip8Rng::with_seed(seed);
+    }
+
+    pub fn load_symbols(&self, filename: &str) -> io::Result<usize> {
+        let mut symbols = self.symbols.write().unwrap();
+        symbols.clear();
+        symbols.load_file(Path::new(filename))
+    }
+
+    //Breakpoints can be given as a label from the symbol file or as a hex address
+    pub fn add_breakpoint(&self, location: &str) -> Result<u16, String> {
+        let address = self
+            .symbols
+            .read()
+            .unwrap()
+            .resolve(location)
+            .ok_or(format!("Unknown breakpoint location {}", location))?;
+        self.breakpoints.write().unwrap().insert(address);
+        Ok(address)
+    }
+
+    //Copy of the whole machine, locks are taken in the same order as run_next
+    pub fn snapshot(&self) -> Chip8State {
+        let memory = self.memory.read().unwrap();
+        let registers = self.registers.read().unwrap();
+        let display = self.display.read().unwrap();
+        let timers = self.timers.read().unwrap();
+        let keyboard = self.keyboard.read().unwrap();
+        Chip8State {
+            memory: memory.clone(),
+            registers: registers.clone(),
+            timers: timers.clone(),
+            display: display.clone(),
+            keyboard: keyboard.clone(),
+            config: *self.config.read().unwrap(),
+            rng: Some(self.rng.read().unwrap().clone()),
+        }
+    }
+
+    pub fn restore(&self, state: Chip8State) {
+        let mut memory = self.memory.write().unwrap();
+        let mut registers = self.registers.write().unwrap();
+        let mut display = self.display.write().unwrap();
+        let mut timers = self.timers.write().unwrap();
+        let mut keyboard = self.keyboard.write().unwrap();
+        *memory = state.memory;
+        *registers = state.registers;
+        //Copied row by row so the generation keeps counting up for the renderers
+        display.set_raw_rows(*state.display.get_raw_rows());
+        *timers = state.timers;
+        *keyboard = state.keyboard;
+        let mut config = self.config.write().unwrap();
+        *config = Chip8Config {
+            timer_hz: config.timer_hz,
+            timing: config.timing,
+            ..state.config
+        };
+        drop(config);
+        //States saved before the rng was stored keep the current generator
+        if let Some(rng) = state.rng {
+            *self.rng.write().unwrap() = rng;
+        }
+    }
+
+    pub fn save_state(&self, filename: &Path) -> io::Result<()> {
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(filename, self.snapshot().to_bytes())
     }
 
-    pub fn load_file_reset(&self, filename: &str) {
+    pub fn load_state(&self, filename: &Path) -> io::Result<()> {
+        let state = Chip8State::from_bytes(&fs::read(filename)?)?;
+        self.restore(state);
+        Ok(())
+    }
+
+    pub fn disassemble(&self, filename: &str, format: Option<Chip8RomFormat>) -> io::Result<()> {
+        let symbols = self.symbols.read().unwrap();
+        let mut decoder = Chip8InstructionDecoder::new();
+        decoder.decode_file(filename, format, &symbols)?;
+        decoder.print_loaded_inst(&symbols);
+        Ok(())
+    }
+
+    //Power on with a program that was already read, see rom_loader::load
+    pub fn load_program_reset(&self, filename: &str, contents: &[u8]) {
         {
             let mut display = self.display.write().unwrap();
             let mut memory = self.memory.write().unwrap();
@@ -71,12 +242,11 @@ impl Chip8 {
             let mut timers = self.timers.write().unwrap();
             let mut instructions = self.instructions.write().unwrap();
             let mut keyboard = self.keyboard.write().unwrap();
+            let mut sprites = self.sprites.write().unwrap();
             //Display
             display.clear();
-            //Memory
-            for byte in memory.raw_array.iter_mut() {
-                *byte = u8::MIN;
-            }
+            //Memory, keeping the built in font
+            *memory = Chip8Memory::new();
             //Registers
             for byte in registers.genral.iter_mut() {
                 *byte = u8::MIN;
@@ -88,31 +258,54 @@ impl Chip8 {
                 *data = u16::MIN;
             }
             registers.memory_address = 0;
-            registers.program_counter = 0;
+            registers.program_counter = Chip8::PROGRAM_START_ADDRESS as u16;
             registers.stack_pointer = 0;
             //Timers
-            timers.delay_timer = 0;
-            timers.sound_timer = 0;
+            *timers = Chip8Timers::new();
+            //Random numbers start again from the same seed
+            self.rng.write().unwrap().reset();
+            //The speed is left as it was
+            let mut clock = self.clock.write().unwrap();
+            let speed = clock.speed;
+            *clock = Chip8Clock::new();
+            clock.speed = speed;
+            drop(clock);
             //Instruction History;
             for inst in instructions.iter_mut() {
                 *inst = " ".to_string();
             }
             //Keyboard
             keyboard.reset_keys();
+            //Sprites seen while running
+            sprites.clear();
         }
-        self.load_file(filename);
+        self.load_program(filename, contents);
+    }
+
+    //Dump sprites found by static analysis of the loaded program together with the ones recorded while running
+    pub fn export_sprites(&self, dir: &Path) -> io::Result<usize> {
+        let memory = self.memory.read().unwrap();
+        let mut sprites = Chip8SpriteTracker::new();
+        sprites.find_static(
+            &memory.raw_array[Chip8::PROGRAM_START_ADDRESS..],
+            Chip8::PROGRAM_START_ADDRESS as u16,
+        );
+        for sprite in self.sprites.read().unwrap().sprites() {
+            sprites.record(sprite.address, sprite.height);
+        }
+        sprites.export(&memory.raw_array, dir)
     }
 
     //Execute current instruction
-    pub fn run_next(
-        s_memory: &SharedMemory,
-        s_registers: &SharedRegisters,
-        s_display: &SharedDisplay,
-        s_timers: &SharedTimers,
-        s_keyboard: &SharedKeyboard,
-    ) -> String {
-        let mut memory = s_memory.write().unwrap();
-        let mut registers = s_registers.write().unwrap();
+    pub fn run_next(&self) -> String {
+        let s_display = &self.display;
+        let s_timers = &self.timers;
+        let s_keyboard = &self.keyboard;
+        let s_sprites = &self.sprites;
+        let mut memory = self.memory.write().unwrap();
+        let mut registers = self.registers.write().unwrap();
+        let symbols = self.symbols.read().unwrap();
+        let quirks = self.config.read().unwrap().quirks;
 
         //Each instruction is 2 bytes long
         let instruction = u16::from_ne_bytes([
@@ -128,15 +321,15 @@ impl Chip8 {
                     //00E0 - CLS
                     //Clear the display.
                     s_display.write().unwrap().clear();
-                    res = format!("CLS");
+                    res = "CLS".to_string();
                 }
                 0x00EE => {
                     // 00EE - RET
                     // Return from a subroutine.
                     // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-                    registers.stack_pointer = registers.stack_pointer - 1;
+                    registers.stack_pointer -= 1;
                     registers.program_counter = registers.stack[registers.stack_pointer as usize];
-                    res = format!("RET");
+                    res = "RET".to_string();
                 }
                 0x0000..=0x0FFF => {
                     // 0nnn - SYS addr
@@ -155,7 +348,7 @@ impl Chip8 {
                 let addr = instruction & 0x0FFF;
                 registers.program_counter = addr;
                 is_inc_program_counter = false;
-                res = format!("JP addr {:#05X}", addr);
+                res = format!("JP addr {}", symbols.format_address(addr));
             }
             0x2 => {
                 // 2nnn - CALL addr
@@ -164,10 +357,10 @@ impl Chip8 {
                 let addr = instruction & 0x0FFF;
                 let sp = registers.stack_pointer as usize;
                 registers.stack[sp] = registers.program_counter;
-                registers.stack_pointer = registers.stack_pointer + 1;
+                registers.stack_pointer += 1;
                 registers.program_counter = addr;
                 is_inc_program_counter = false;
-                res = format!("CALL addr {:#05X}", addr);
+                res = format!("CALL addr {}", symbols.format_address(addr));
             }
             0x3 => {
                 // 3xkk - SE Vx, byte
@@ -176,7 +369,7 @@ impl Chip8 {
                 let byte = instruction & 0x00FF;
                 let vx = (instruction >> 8) & 0x000F;
                 if registers.genral[vx as usize] == byte as u8 {
-                    registers.program_counter = registers.program_counter + 2;
+                    registers.program_counter += 2;
                 }
                 res = format!("SE V{}, {:#04X}", vx, byte);
             }
@@ -187,7 +380,7 @@ impl Chip8 {
                 let byte = instruction & 0x00FF;
                 let vx = (instruction >> 8) & 0x000F;
                 if registers.genral[vx as usize] != byte as u8 {
-                    registers.program_counter = registers.program_counter + 2;
+                    registers.program_counter += 2;
                 }
                 res = format!("SNE V{}, {:#04X}", vx, byte);
             }
@@ -198,7 +391,7 @@ impl Chip8 {
                 let vx = (instruction >> 8) & 0x000F;
                 let vy = (instruction >> 4) & 0x000F;
                 if registers.genral[vx as usize] == registers.genral[vy as usize] {
-                    registers.program_counter = registers.program_counter + 2;
+                    registers.program_counter += 2;
                 }
                 res = format!("SE V{}, V{}", vx, vy);
             }
@@ -239,8 +432,10 @@ impl Chip8 {
                         // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx. A bitwise OR compares the corrseponding bits from two values, and if either bit is 1, then the same bit in the result is also 1. Otherwise, it is 0.
                         let vx = (instruction >> 8) & 0x000F;
                         let vy = (instruction >> 4) & 0x000F;
-                        registers.genral[vx as usize] =
-                            registers.genral[vx as usize] | registers.genral[vy as usize];
+                        registers.genral[vx as usize] |= registers.genral[vy as usize];
+                        if quirks.logic_resets_vf {
+                            registers.genral[15] = 0;
+                        }
                         res = format!("OR V{}, V{}", vx, vy);
                     }
                     0x2 => {
@@ -249,8 +444,10 @@ impl Chip8 {
                         // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx. A bitwise AND compares the corrseponding bits from two values, and if both bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
                         let vx = (instruction >> 8) & 0x000F;
                         let vy = (instruction >> 4) & 0x000F;
-                        registers.genral[vx as usize] =
-                            registers.genral[vx as usize] & registers.genral[vy as usize];
+                        registers.genral[vx as usize] &= registers.genral[vy as usize];
+                        if quirks.logic_resets_vf {
+                            registers.genral[15] = 0;
+                        }
                         res = format!("AND V{}, V{}", vx, vy);
                     }
                     0x3 => {
@@ -259,8 +456,10 @@ impl Chip8 {
                         // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx. An exclusive OR compares the corrseponding bits from two values, and if the bits are not both the same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
                         let vx = (instruction >> 8) & 0x000F;
                         let vy = (instruction >> 4) & 0x000F;
-                        registers.genral[vx as usize] =
-                            registers.genral[vx as usize] ^ registers.genral[vy as usize];
+                        registers.genral[vx as usize] ^= registers.genral[vy as usize];
+                        if quirks.logic_resets_vf {
+                            registers.genral[15] = 0;
+                        }
                         res = format!("XOR V{}, V{}", vx, vy);
                     }
                     0x4 => {
@@ -293,8 +492,9 @@ impl Chip8 {
                         // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
                         let vx = (instruction >> 8) & 0x000F;
                         let vy = (instruction >> 4) & 0x000F;
-                        registers.genral[15] = registers.genral[vx as usize] & 1;
-                        registers.genral[vx as usize] = registers.genral[vx as usize] >> 1;
+                        let source = if quirks.shift_uses_vy { vy } else { vx };
+                        registers.genral[15] = registers.genral[source as usize] & 1;
+                        registers.genral[vx as usize] = registers.genral[source as usize] >> 1;
                         res = format!("SHR V{}, V{}", vx, vy);
                     }
                     0x7 => {
@@ -314,8 +514,11 @@ impl Chip8 {
                         // Set Vx = Vx SHL 1.
                         // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
                         let vx = (instruction >> 8) & 0x000F;
-                        registers.genral[15] = ((registers.genral[vx as usize] & 0x80) > 0) as u8;
-                        registers.genral[vx as usize] = registers.genral[vx as usize] << 1;
+                        let vy = (instruction >> 4) & 0x000F;
+                        let source = if quirks.shift_uses_vy { vy } else { vx };
+                        registers.genral[15] =
+                            ((registers.genral[source as usize] & 0x80) > 0) as u8;
+                        registers.genral[vx as usize] = registers.genral[source as usize] << 1;
                         res = format!("SHL V{}", vx);
                     }
                     _ => {}
@@ -328,7 +531,7 @@ impl Chip8 {
                 let vx = (instruction >> 8) & 0x000F;
                 let vy = (instruction >> 4) & 0x000F;
                 if registers.genral[vx as usize] != registers.genral[vy as usize] {
-                    registers.program_counter = registers.program_counter + 2;
+                    registers.program_counter += 2;
                 }
                 res = format!("SNE V{}, V{}", vx, vy);
             }
@@ -338,16 +541,18 @@ impl Chip8 {
                 // The value of register I is set to nnn.
                 let addr = instruction & 0x0FFF;
                 registers.memory_address = addr;
-                res = format!("LD I, addr {:#05X}", addr);
+                res = format!("LD I, addr {}", symbols.format_address(addr));
             }
             0xB => {
                 // Bnnn - JP V0, addr
                 // Jump to location nnn + V0.
                 // The program counter is set to nnn plus the value of V0.
                 let addr = instruction & 0x0FFF;
-                registers.program_counter = (registers.genral[0] as u16) + addr;
+                let offset_register = if quirks.jump_uses_vx { addr >> 8 } else { 0 };
+                registers.program_counter =
+                    (registers.genral[offset_register as usize] as u16) + addr;
                 is_inc_program_counter = false;
-                res = format!("JP V0, addr {:#05X}", addr);
+                res = format!("JP V0, addr {}", symbols.format_address(addr));
             }
             0xC => {
                 // Cxkk - RND Vx, byte
@@ -355,7 +560,7 @@ impl Chip8 {
                 // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
                 let byte = (instruction & 0x00FF) as u8;
                 let vx = (instruction >> 8) & 0x000F;
-                let r: u8 = rand::random();
+                let r: u8 = self.rng.write().unwrap().next_byte();
                 registers.genral[vx as usize] = r & byte;
                 res = format!("RND V{}, {:#04X}", vx, byte);
             }
@@ -369,7 +574,22 @@ impl Chip8 {
                 let number_bytes = instruction & 0x000F;
                 let vx = (instruction >> 8) & 0x000F;
                 let vy = (instruction >> 4) & 0x000F;
+                //With the display wait quirk the sprite is drawn at the next vertical blank, until then the cpu stays on this instruction
+                if quirks.display_wait {
+                    let mut timers = s_timers.write().unwrap();
+                    if !timers.vblank {
+                        return format!(
+                            "DRW V{}, V{}, {:#03X} (waiting for vblank)",
+                            vx, vy, number_bytes
+                        );
+                    }
+                    timers.vblank = false;
+                }
                 let mut display = s_display.write().unwrap();
+                s_sprites
+                    .write()
+                    .unwrap()
+                    .record(registers.memory_address, number_bytes as u8);
 
                 //Simple implementation can speedup.
                 //Assuming sprite resolution is 8xn.
@@ -378,26 +598,27 @@ impl Chip8 {
                 let sprite = &memory.raw_array[registers.memory_address as usize
                     ..(registers.memory_address + number_bytes) as usize];
 
+                //The starting position always wraps, the rest of the sprite wraps or clips depending on quirks
+                let start_x = registers.genral[vx as usize] as usize % Chip8Display::WIDTH;
+                let start_y = registers.genral[vy as usize] as usize % Chip8Display::HEIGHT;
                 for (index, s_data) in sprite.iter().enumerate() {
                     for bit_index in 0u8..8 {
                         let bit_mask = 128 >> bit_index;
                         let bit = (s_data & bit_mask) > 0;
+                        let x = start_x + bit_index as usize;
+                        let y = start_y + index;
+                        if quirks.clip_sprites
+                            && (x >= Chip8Display::WIDTH || y >= Chip8Display::HEIGHT)
+                        {
+                            continue;
+                        }
                         if bit {
-                            let current = display.get_pixel(
-                                registers.genral[vx as usize] as usize + bit_index as usize,
-                                registers.genral[vy as usize] as usize + index,
-                            );
-                            if (current == bit) && (bit == true) {
-                                display.unset_pixel(
-                                    registers.genral[vx as usize] as usize + bit_index as usize,
-                                    registers.genral[vy as usize] as usize + index,
-                                );
+                            let current = display.get_pixel(x, y);
+                            if (current == bit) && (bit) {
+                                display.unset_pixel(x, y);
                                 registers.genral[15] = 1; //Set VF = 1 for collision
                             } else {
-                                display.set_pixel(
-                                    registers.genral[vx as usize] as usize + bit_index as usize,
-                                    registers.genral[vy as usize] as usize + index,
-                                );
+                                display.set_pixel(x, y);
                             }
                         }
                     }
@@ -417,7 +638,7 @@ impl Chip8 {
                             .unwrap()
                             .get_key(registers.genral[vx as usize])
                         {
-                            registers.program_counter = registers.program_counter + 2;
+                            registers.program_counter += 2;
                         }
                         res = format!("SKP V{}", vx);
                     }
@@ -431,7 +652,7 @@ impl Chip8 {
                             .unwrap()
                             .get_key(registers.genral[vx as usize])
                         {
-                            registers.program_counter = registers.program_counter + 2;
+                            registers.program_counter += 2;
                         }
                         res = format!("SKNP V{}", vx);
                     }
@@ -501,7 +722,7 @@ impl Chip8 {
                         memory.raw_array[(registers.memory_address + 1) as usize] =
                             (registers.genral[vx as usize] / 10) % 10;
                         memory.raw_array[(registers.memory_address + 2) as usize] =
-                            (registers.genral[vx as usize] / 1) % 10;
+                            registers.genral[vx as usize] % 10;
                         res = format!(
                             "LD B, V{} (I: {}, I+1: {}, I+2:{})",
                             vx,
@@ -520,6 +741,9 @@ impl Chip8 {
                             memory.raw_array[(current_address + data) as usize] =
                                 registers.genral[data as usize];
                         }
+                        if quirks.load_store_increments_i {
+                            registers.memory_address = current_address + vx + 1;
+                        }
                         res = format!("LD [I], V{}", vx);
                     }
                     0x65 => {
@@ -532,6 +756,9 @@ impl Chip8 {
                             registers.genral[data as usize] =
                                 memory.raw_array[(current_address + data) as usize];
                         }
+                        if quirks.load_store_increments_i {
+                            registers.memory_address = current_address + vx + 1;
+                        }
                         res = format!("LD V{}, [I]", vx);
                     }
                     _ => {}
@@ -540,15 +767,193 @@ impl Chip8 {
             _ => {}
         }
 
-        res = format!("{:#05X}: {}", registers.program_counter, res);
+        res = format!(
+            "{}: {}",
+            symbols.format_address(registers.program_counter),
+            res
+        );
         //Increment in program counter after instruction is processed
         if is_inc_program_counter {
-            registers.program_counter = registers.program_counter + 2;
+            registers.program_counter += 2;
         }
 
         res
     }
 
+    //Decrement the timers and move on to the next frame, recording or replaying keypad input
+    pub fn tick_timers(&self) {
+        let started = Instant::now();
+        let mut timers = self.timers.write().unwrap();
+        timers.tick();
+        let frame = timers.frame;
+        drop(timers);
+
+        //Keys change only here, between frames, from the movie being played or from the UI
+        match self.playback.read().unwrap().as_ref() {
+            Some(movie) => {
+                if let Some(key_map) = movie.key_map_at(frame) {
+                    self.keyboard.write().unwrap().set_key_map(key_map);
+                }
+            }
+            None => self.keyboard.write().unwrap().apply_pending(),
+        }
+        //Hash of the live machine at every frame boundary, so the movie ends with the state recording stopped in.
+        //The snapshot is taken before locking the recording to keep the lock order
+        if self.recording.read().unwrap().is_some() {
+            let state = self.snapshot();
+            if let Some(movie) = self.recording.write().unwrap().as_mut() {
+                movie.record(frame, state.keyboard.get_key_map());
+                movie.final_hash = state.hash();
+            }
+        }
+        if let Some(gif) = self.gif.write().unwrap().as_mut() {
+            gif.capture(&self.display.read().unwrap());
+        }
+        let mut stats = self.stats.write().unwrap();
+        stats.timer_ticks.add(1);
+        stats.cpu_busy.add_duration(started.elapsed());
+    }
+
+    pub fn start_gif(&self) {
+        *self.gif.write().unwrap() = Some(Chip8GifRecorder::new());
+    }
+
+    pub fn stop_gif(&self) -> Option<Chip8GifRecorder> {
+        self.gif.write().unwrap().take()
+    }
+
+    //Run one frame of the master clock without waiting for it: the cycles owed for cpu_hz followed by a single timer tick
+    //The cycles left after a sprite waiting for vblank are lost, like on the VIP
+    pub fn run_frame(&self) {
+        self.run_cycles(None);
+        self.tick_timers();
+    }
+
+    //True while the next instruction is a Dxyn held back by the display wait quirk
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        let memory = self.memory.read().unwrap();
+        let registers = self.registers.read().unwrap();
+        if !self.config.read().unwrap().quirks.display_wait {
+            return false;
+        }
+        //A PC outside memory isn't waiting, fetching there is a fault
+        let pc = registers.program_counter as usize;
+        match memory.raw_array.get(pc..pc + 2) {
+            Some(bytes) => bytes[0] >> 4 == 0xD && !self.timers.read().unwrap().vblank,
+            None => false,
+        }
+    }
+
+    //Recording starts from the current state, call load_program_reset first to record from power on
+    pub fn start_recording(&self) {
+        let state = self.snapshot();
+        let seed = state.rng.as_ref().unwrap().seed();
+        let mut movie = Chip8Movie::new(seed, state.config);
+        movie.record(state.timers.frame, state.keyboard.get_key_map());
+        movie.final_hash = state.hash();
+        *self.recording.write().unwrap() = Some(movie);
+    }
+
+    pub fn stop_recording(&self) -> Option<Chip8Movie> {
+        self.recording.write().unwrap().take()
+    }
+
+    //Playback replaces keypad input with the movie, call load_program_reset first
+    pub fn start_playback(&self, movie: Chip8Movie) {
+        let mut config = self.config.write().unwrap();
+        *config = Chip8Config {
+            timer_hz: config.timer_hz,
+            ..movie.config
+        };
+        drop(config);
+        self.clock.write().unwrap().reset();
+        *self.rng.write().unwrap() = Chip8Rng::with_seed(movie.seed);
+        if let Some(key_map) = movie.key_map_at(self.timers.read().unwrap().frame) {
+            self.keyboard.write().unwrap().set_key_map(key_map);
+        }
+        *self.playback.write().unwrap() = Some(movie);
+    }
+
+    //Playback stops by itself once the last frame of the movie is reached
+    pub fn is_playing(&self) -> bool {
+        let mut playback = self.playback.write().unwrap();
+        let frame = self.timers.read().unwrap().frame;
+        if playback.as_ref().is_some_and(|movie| frame >= movie.frames) {
+            *playback = None;
+        }
+        playback.is_some()
+    }
+
+    //Run frames back to back until the movie being played ends
+    pub fn run_playback(&self) {
+        while self.is_playing() {
+            self.run_frame();
+        }
+    }
+
+    //Headless run for scripts: frames back to back like run_frame, checking before every instruction for the
+    //limits, halts, breakpoints and faults that end the run. Unknown instructions are faults too
+    pub fn run_batch(&self, batch: &Chip8Batch) -> Chip8BatchResult {
+        let mut instructions = 0;
+        let mut keys = batch.keys.iter().peekable();
+        let end = 'frames: loop {
+            let frame = self.timers.read().unwrap().frame;
+            if frame >= batch.frames {
+                break Chip8BatchEnd::Frames;
+            }
+            while let Some((_, key_map)) = keys.next_if(|(at, _)| *at <= frame) {
+                self.keyboard.write().unwrap().set_key_map(*key_map);
+            }
+            let config = *self.config.read().unwrap();
+            let budget = self.clock.write().unwrap().frame_budget(&config);
+            let mut used = 0;
+            while used < budget && !self.is_waiting_for_vblank() {
+                if batch.instructions.is_some_and(|max| instructions >= max) {
+                    break 'frames Chip8BatchEnd::Instructions;
+                }
+                let memory = self.memory.read().unwrap();
+                let registers = self.registers.read().unwrap();
+                let pc = registers.program_counter;
+                let instruction = match memory.raw_array.get(pc as usize..pc as usize + 2) {
+                    Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+                    None => {
+                        break 'frames Chip8BatchEnd::Fault(format!(
+                            "program counter {:03X} is outside memory",
+                            pc
+                        ))
+                    }
+                };
+                if let Some(fault) =
+                    Chip8Batch::fault(instruction, &registers, memory.raw_array.len())
+                {
+                    break 'frames Chip8BatchEnd::Fault(fault);
+                }
+                drop(registers);
+                drop(memory);
+                if let Some(halt) = batch
+                    .halts
+                    .iter()
+                    .find(|halt| halt.matches(instruction, pc))
+                {
+                    break 'frames Chip8BatchEnd::Halt(*halt);
+                }
+                if self.breakpoints.read().unwrap().contains(pc) {
+                    break 'frames Chip8BatchEnd::Breakpoint(pc);
+                }
+                used += self.next_cost(&config).unwrap_or(0);
+                self.run_next();
+                instructions += 1;
+            }
+            self.clock.write().unwrap().end_frame(used, budget);
+            self.tick_timers();
+        };
+        Chip8BatchResult {
+            end,
+            frames: self.timers.read().unwrap().frame,
+            instructions,
+        }
+    }
+
     //Start a thread to print display buffer to stdout every second (for debug purpose)
     pub fn _start_display_thread(
         &self,
@@ -572,7 +977,7 @@ impl Chip8 {
                 match msg {
                 Chip8ControlMessage::Start => { is_running = true; },
                 Chip8ControlMessage::Stop => { is_running = false; },
-                Chip8ControlMessage::Step => {
+                Chip8ControlMessage::Step | Chip8ControlMessage::Frame => {
                 print!("{}[2J", 27 as char);
                 println!("{}", m_display.read().unwrap());
                 },
@@ -583,92 +988,265 @@ impl Chip8 {
         })
     }
 
-    pub fn start_timers_thread(
+    //Master clock: every frame runs the cpu cycles owed for cpu_hz, ticks the timers once and sleeps until
+    //the next frame is due. Deadlines come from the frame count so the frame rate is exact on average,
+    //late frames are caught up by running the next ones back to back and a schedule more than
+    //MAX_CATCH_UP_FRAMES behind starts over from now. The speed of the clock scales the frame rate, turbo
+    //runs frames back to back. The cpu and the timers are started and stopped through their own channel
+    pub fn start_clock_thread(
         &self,
-        mut rx: Receiver<Chip8ControlMessage>,
+        mut cpu_rx: Receiver<Chip8ControlMessage>,
+        mut timer_rx: Receiver<Chip8ControlMessage>,
     ) -> tokio::task::JoinHandle<()> {
-        let m_timers = self.timers.clone();
+        let m_sys = self.clone();
         tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_millis(17));
-            interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
-            tokio::pin!(interval);
-            let mut is_running = false;
+            let mut cpu_running = false;
+            let mut timers_running = false;
+            // Lets the instruction under a breakpoint run once execution is resumed
+            let mut skip_breakpoint = false;
+            let mut frame_hz = m_sys.config.read().unwrap().timer_hz;
+            let mut speed = m_sys.clock.read().unwrap().speed;
+            let mut start = time::Instant::now();
+            let mut frame: u64 = 0;
             loop {
+                let deadline = match speed {
+                    Chip8Speed::Percent(percent) => {
+                        start + Chip8Clock::frame_offset(frame + 1, frame_hz, percent)
+                    }
+                    Chip8Speed::Turbo => time::Instant::now(),
+                };
                 tokio::select! {
-                _ = interval.tick() => {
-                if is_running {
-                let mut timers = m_timers.write().unwrap();
-                if timers.delay_timer > 0 {
-                timers.delay_timer = timers.delay_timer - 1;
-                //println!("{}", timers.delay_timer);
-                }
-                if timers.sound_timer > 0 {
-                timers.sound_timer = timers.sound_timer - 1;
-                //println!("{}", timers.sound_timer);
-                }
-                }
-                },
-                Some(msg) = rx.recv() => {
-                match msg {
-                Chip8ControlMessage::Start => { is_running = true; },
-                Chip8ControlMessage::Stop => { is_running = false; },
-                Chip8ControlMessage::Step => {
-                let mut timers = m_timers.write().unwrap();
-                if timers.delay_timer > 0 {
-                timers.delay_timer = timers.delay_timer - 1;
-                //println!("{}", timers.delay_timer);
-                }
-                if timers.sound_timer > 0 {
-                timers.sound_timer = timers.sound_timer - 1;
-                //println!("{}", timers.sound_timer);
-                }
-                },
-                }
-                }
+                    _ = time::sleep_until(deadline) => {
+                        //Turbo runs as many frames as fit in a slice, at other speeds this is one frame
+                        loop {
+                            frame += 1;
+                            if cpu_running {
+                                cpu_running = m_sys.run_cycles(Some(&mut skip_breakpoint));
+                            }
+                            if timers_running {
+                                m_sys.tick_timers();
+                            }
+                            let is_paused = !cpu_running && !timers_running;
+                            if speed != Chip8Speed::Turbo || is_paused || deadline.elapsed() >= Chip8Clock::TURBO_SLICE {
+                                break;
+                            }
+                        }
+                        let config_hz = m_sys.config.read().unwrap().timer_hz;
+                        let config_speed = m_sys.clock.read().unwrap().speed;
+                        let behind = time::Instant::now().saturating_duration_since(deadline);
+                        let max_behind = Chip8Clock::frame_offset(Chip8Clock::MAX_CATCH_UP_FRAMES, frame_hz, 100);
+                        if config_hz != frame_hz || config_speed != speed || behind > max_behind {
+                            frame_hz = config_hz;
+                            speed = config_speed;
+                            start = time::Instant::now();
+                            frame = 0;
+                        }
+                    },
+                    Some(msg) = cpu_rx.recv() => match msg {
+                        Chip8ControlMessage::Start => {
+                            cpu_running = true;
+                            skip_breakpoint = m_sys.breakpoints.write().unwrap().hit.take().is_some();
+                        }
+                        Chip8ControlMessage::Stop => cpu_running = false,
+                        //Single steps happen with the timers stopped, so they take the keys pressed so far unless a movie is recording
+                        Chip8ControlMessage::Step => {
+                            if m_sys.recording.read().unwrap().is_none() {
+                                m_sys.keyboard.write().unwrap().apply_pending();
+                            }
+                            m_sys.run_next_logged();
+                        }
+                        //Pauses both and runs a single frame, breakpoints included
+                        Chip8ControlMessage::Frame => {
+                            cpu_running = false;
+                            timers_running = false;
+                            m_sys.breakpoints.write().unwrap().hit = None;
+                            let mut skip_breakpoint = true;
+                            m_sys.run_cycles(Some(&mut skip_breakpoint));
+                            m_sys.tick_timers();
+                        }
+                    },
+                    Some(msg) = timer_rx.recv() => match msg {
+                        Chip8ControlMessage::Start => timers_running = true,
+                        Chip8ControlMessage::Stop => timers_running = false,
+                        Chip8ControlMessage::Step | Chip8ControlMessage::Frame => m_sys.tick_timers(),
+                    },
                 }
             }
         })
     }
 
-    pub fn start_cpu_thread(
-        &self,
-        mut rx: Receiver<Chip8ControlMessage>,
-    ) -> tokio::task::JoinHandle<()> {
-        let m_display = self.display.clone();
-        let m_timers = self.timers.clone();
-        let m_memory = self.memory.clone();
-        let m_registers = self.registers.clone();
-        let m_instructions = self.instructions.clone(); // Used to draw list of instructions for debug purposes
-        let m_keyboard = self.keyboard.clone();
-        tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_millis(2));
-            interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
-            tokio::pin!(interval);
-            let mut is_running = false;
-            loop {
-                tokio::select! {
-                _ = interval.tick() => {
-                if is_running {
-                let res = Chip8::run_next(&m_memory,&m_registers,&m_display,&m_timers,&m_keyboard);
-                let mut i = m_instructions.write().unwrap();
-                i.push_front(res);
-                i.pop_back();
-                }
-                },
-                Some(msg) = rx.recv() => {
-                match msg {
-                Chip8ControlMessage::Start => { is_running = true; },
-                Chip8ControlMessage::Stop => { is_running = false; },
-                Chip8ControlMessage::Step => {
-                let res = Chip8::run_next(&m_memory,&m_registers,&m_display,&m_timers,&m_keyboard);
-                let mut i = m_instructions.write().unwrap();
-                i.push_front(res);
-                i.pop_back();
-                },
-                }
-                }
+    //Cpu side of a frame: instructions until the budget of the frame is spent or a sprite waits for vblank.
+    //The scheduler passes skip_breakpoint to stop at breakpoints, false is returned when one was hit
+    fn run_cycles(&self, mut skip_breakpoint: Option<&mut bool>) -> bool {
+        let started = Instant::now();
+        let config = *self.config.read().unwrap();
+        let budget = self.clock.write().unwrap().frame_budget(&config);
+        let mut used = 0;
+        let mut count = 0;
+        let mut is_running = true;
+        while used < budget {
+            if self.is_waiting_for_vblank() {
+                break;
+            }
+            if let Some(skip_breakpoint) = skip_breakpoint.as_deref_mut() {
+                let pc = self.registers.read().unwrap().program_counter;
+                let mut breakpoints = self.breakpoints.write().unwrap();
+                if !*skip_breakpoint && breakpoints.contains(pc) {
+                    breakpoints.hit = Some(pc);
+                    is_running = false;
+                    break;
                 }
+                *skip_breakpoint = false;
             }
+            //The cpu stops instead of running off the end of memory
+            let Some(cost) = self.next_cost(&config) else {
+                is_running = false;
+                break;
+            };
+            used += cost;
+            self.run_next_logged();
+            count += 1;
+        }
+        self.clock.write().unwrap().end_frame(used, budget);
+        let mut stats = self.stats.write().unwrap();
+        stats.instructions.add(count);
+        stats.cpu_busy.add_duration(started.elapsed());
+        is_running
+    }
+
+    //What the instruction at PC takes out of the frame budget, None when PC is outside memory
+    fn next_cost(&self, config: &Chip8Config) -> Option<u32> {
+        let memory = self.memory.read().unwrap();
+        let registers = self.registers.read().unwrap();
+        let pc = registers.program_counter as usize;
+        let bytes = memory.raw_array.get(pc..pc + 2)?;
+        Some(match config.timing {
+            Chip8Timing::Instructions => 1,
+            Chip8Timing::Vip => Chip8VipTiming::cost(
+                u16::from_be_bytes([bytes[0], bytes[1]]),
+                &registers,
+                &self.keyboard.read().unwrap(),
+            ),
         })
     }
+
+    //Run the next instruction and add it to the list of instructions shown for debugging
+    fn run_next_logged(&self) {
+        let res = self.run_next();
+        let mut instructions = self.instructions.write().unwrap();
+        instructions.push_front(res);
+        instructions.pop_back();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Machine running program, written to a file of its own as the machine loads ROMs by name
+    fn machine(name: &str, program: &[u8]) -> Chip8 {
+        let path =
+            std::env::temp_dir().join(format!("chip8-test-{}-{}.ch8", std::process::id(), name));
+        fs::write(&path, program).unwrap();
+        let sys = Chip8::new(&path.to_string_lossy(), None).unwrap();
+        fs::remove_file(&path).unwrap();
+        sys
+    }
+
+    #[test]
+    fn batch_stops_at_an_unknown_instruction() {
+        let sys = machine("unknown", &[0x60, 0x01, 0xE0, 0x00, 0x12, 0x04]);
+        let result = sys.run_batch(&Chip8Batch::new());
+        assert_eq!(
+            result.end,
+            Chip8BatchEnd::Fault("unknown instruction E000 at 202".to_string())
+        );
+        assert_eq!(result.instructions, 1);
+        assert_eq!(sys.registers.read().unwrap().program_counter, 0x202);
+    }
+
+    #[test]
+    fn batch_stops_at_a_stack_underflow() {
+        let sys = machine("underflow", &[0x00, 0xEE]);
+        let result = sys.run_batch(&Chip8Batch::new());
+        assert!(result.end.is_fault());
+        assert_eq!(result.instructions, 0);
+    }
+
+    #[test]
+    fn batch_faults_when_pc_leaves_memory_while_waiting_for_vblank() {
+        for (program, pc) in [
+            ([0x60, 0xFF, 0xBF, 0x01], 0x1000),
+            ([0x60, 0xFF, 0xBF, 0x00], 0xFFF),
+        ] {
+            let sys = machine("off-the-end", &program);
+            sys.config.write().unwrap().quirks.display_wait = true;
+            let result = sys.run_batch(&Chip8Batch::new());
+            assert_eq!(
+                result.end,
+                Chip8BatchEnd::Fault(format!("program counter {:03X} is outside memory", pc))
+            );
+            assert!(result.end.is_fault());
+            assert_eq!(result.instructions, 2);
+        }
+    }
+
+    #[test]
+    fn batch_runs_to_the_frame_limit() {
+        let sys = machine("frames", &[0x70, 0x01, 0x12, 0x00]);
+        let mut batch = Chip8Batch::new();
+        batch.frames = 3;
+        let result = sys.run_batch(&batch);
+        assert_eq!(result.end, Chip8BatchEnd::Frames);
+        assert_eq!(result.frames, 3);
+    }
+
+    //One frame as run_cycles runs it, with the UI pressing or releasing key 0 halfway through
+    fn run_frame_pressing(sys: &Chip8, press: Option<bool>) {
+        let config = *sys.config.read().unwrap();
+        let budget = sys.clock.write().unwrap().frame_budget(&config);
+        for used in 0..budget {
+            if used == budget / 2 {
+                let mut keyboard = sys.keyboard.write().unwrap();
+                match press {
+                    Some(true) => keyboard.set_pending_key(0),
+                    Some(false) => keyboard.reset_pending_keys(),
+                    None => {}
+                }
+            }
+            sys.run_next();
+        }
+        sys.clock.write().unwrap().end_frame(budget, budget);
+        sys.tick_timers();
+    }
+
+    #[test]
+    fn replays_match_recordings_with_keys_changing_mid_frame() {
+        //Counts in V2 the instructions that saw key 0 down and in V3 all of them
+        let program = [0xE0, 0xA1, 0x72, 0x01, 0x73, 0x01, 0x12, 0x00];
+        let sys = machine("record", &program);
+        sys.start_recording();
+        for frame in 0..30 {
+            let press = match frame % 7 {
+                1 | 4 => Some(true),
+                3 | 6 => Some(false),
+                _ => None,
+            };
+            let before = sys.registers.read().unwrap().genral[2];
+            run_frame_pressing(&sys, press);
+            //A key pressed in frame 1 is seen from frame 2 on
+            if frame == 1 {
+                assert_eq!(sys.registers.read().unwrap().genral[2], before);
+            }
+        }
+        let movie = sys.stop_recording().unwrap();
+        let recorded = sys.registers.read().unwrap().genral;
+        assert!(recorded[2] > 0);
+
+        let replay = machine("replay", &program);
+        replay.start_playback(movie.clone());
+        replay.run_playback();
+        assert_eq!(replay.registers.read().unwrap().genral, recorded);
+        assert_eq!(replay.snapshot().hash(), movie.final_hash);
+    }
 }
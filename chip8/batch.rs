@@ -0,0 +1,308 @@
+// Headless batch runs for scripts and CI: the machine runs frames like the clock would, without waiting,
+// until a frame or instruction limit, a halt condition, a breakpoint or a fault stops it.
+//
+// Key scripts hold one change of the keypad per line, the frame it happens on and the keys held from then on:
+//
+//     # frame keys
+//     60 5
+//     64 -
+//     120 4 6
+//
+// Keys are hex keypad keys, `-` releases them all. Lines must be in frame order.
+use super::registers::Chip8Registers;
+use super::save_state::Chip8State;
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Halt {
+    // A jump to itself, how most programs end
+    Loop,
+
+    // 00FD, the SUPER-CHIP exit instruction
+    Exit,
+
+    // Fx0A waiting for a key that no script will press
+    KeyWait,
+}
+
+impl Chip8Halt {
+    pub fn from_name(name: &str) -> Option<Chip8Halt> {
+        match name {
+            "loop" => Some(Chip8Halt::Loop),
+            "exit" => Some(Chip8Halt::Exit),
+            "key" => Some(Chip8Halt::KeyWait),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chip8Halt::Loop => "loop",
+            Chip8Halt::Exit => "exit",
+            Chip8Halt::KeyWait => "key",
+        }
+    }
+
+    //True when instruction at pc, not yet run, is this halt
+    pub fn matches(self, instruction: u16, pc: u16) -> bool {
+        match self {
+            Chip8Halt::Loop => instruction == 0x1000 | pc,
+            Chip8Halt::Exit => instruction == 0x00FD,
+            Chip8Halt::KeyWait => instruction & 0xF0FF == 0xF00A,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8BatchEnd {
+    Frames,
+    Instructions,
+    Halt(Chip8Halt),
+    Breakpoint(u16),
+    Fault(String),
+}
+
+impl Chip8BatchEnd {
+    pub fn is_fault(&self) -> bool {
+        matches!(self, Chip8BatchEnd::Fault(_))
+    }
+}
+
+impl std::fmt::Display for Chip8BatchEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Chip8BatchEnd::Frames => write!(f, "frame limit reached"),
+            Chip8BatchEnd::Instructions => write!(f, "instruction limit reached"),
+            Chip8BatchEnd::Halt(halt) => write!(f, "halted ({})", halt.name()),
+            Chip8BatchEnd::Breakpoint(address) => write!(f, "breakpoint at {:03X}", address),
+            Chip8BatchEnd::Fault(fault) => write!(f, "fault: {}", fault),
+        }
+    }
+}
+
+pub struct Chip8Batch {
+    pub frames: u64,
+    pub instructions: Option<u64>,
+    pub halts: Vec<Chip8Halt>,
+
+    // Frame and key map, in frame order
+    pub keys: Vec<(u64, u16)>,
+}
+
+impl Chip8Batch {
+    // A minute of frames when no limit is given, so a run always ends
+    pub const DEFAULT_FRAMES: u64 = 3600;
+
+    pub fn new() -> Chip8Batch {
+        Chip8Batch {
+            frames: Chip8Batch::DEFAULT_FRAMES,
+            instructions: None,
+            halts: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    //Key script as described at the top of this file
+    pub fn parse_keys(contents: &str) -> Result<Vec<(u64, u16)>, String> {
+        let mut keys: Vec<(u64, u16)> = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let mut fields = line.split_whitespace();
+            let frame = match fields.next() {
+                Some(frame) => frame
+                    .parse::<u64>()
+                    .map_err(|_| format!("line {}: invalid frame {}", index + 1, frame))?,
+                None => continue,
+            };
+            if keys.last().is_some_and(|(last, _)| *last > frame) {
+                return Err(format!("line {}: frame {} out of order", index + 1, frame));
+            }
+            let mut key_map = 0;
+            for key in fields {
+                if key == "-" {
+                    continue;
+                }
+                let key = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|key| *key < 16)
+                    .ok_or(format!("line {}: invalid key {}", index + 1, key))?;
+                key_map |= 1 << key;
+            }
+            keys.push((frame, key_map));
+        }
+        Ok(keys)
+    }
+
+    //Instructions run_next has no case for, it would skip them without doing anything
+    fn is_known(instruction: u16) -> bool {
+        match instruction >> 12 {
+            0x8 => matches!(instruction & 0x000F, 0x0..=0x7 | 0xE),
+            0xE => matches!(instruction & 0x00FF, 0x9E | 0xA1),
+            0xF => matches!(
+                instruction & 0x00FF,
+                0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x33 | 0x55 | 0x65
+            ),
+            _ => true,
+        }
+    }
+
+    //What would make the instruction at PC panic or do nothing, checked before it runs. PC itself is checked
+    //when fetching
+    pub fn fault(
+        instruction: u16,
+        registers: &Chip8Registers,
+        memory_size: usize,
+    ) -> Option<String> {
+        let pc = registers.program_counter;
+        let address = registers.memory_address as usize;
+        let x = ((instruction >> 8) & 0x000F) as usize;
+        let reads = match instruction & 0xF0FF {
+            0xF033 => 3,
+            0xF055 | 0xF065 => x + 1,
+            _ if instruction >> 12 == 0xD => (instruction & 0x000F) as usize,
+            _ => 0,
+        };
+        if !Chip8Batch::is_known(instruction) {
+            Some(format!(
+                "unknown instruction {:04X} at {:03X}",
+                instruction, pc
+            ))
+        } else if instruction == 0x00EE && registers.stack_pointer == 0 {
+            Some(format!("stack underflow at {:03X}", pc))
+        } else if instruction >> 12 == 0x2
+            && registers.stack_pointer as usize >= registers.stack.len()
+        {
+            Some(format!("stack overflow at {:03X}", pc))
+        } else if address + reads > memory_size {
+            Some(format!(
+                "I={:03X} reaches past the end of memory at {:03X}",
+                address, pc
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+//How a batch run ended, printed or written out by the caller
+pub struct Chip8BatchResult {
+    pub end: Chip8BatchEnd,
+    pub frames: u64,
+    pub instructions: u64,
+}
+
+impl Chip8BatchResult {
+    //Stop reason, the screen as drawn by the caller, registers and state hash as text
+    pub fn report(&self, state: &Chip8State, screen: &str) -> String {
+        let registers = &state.registers;
+        let mut res = String::new();
+        writeln!(
+            &mut res,
+            "Stopped: {} after {} frames and {} instructions",
+            self.end, self.frames, self.instructions
+        )
+        .unwrap();
+        write!(&mut res, "{}", screen).unwrap();
+        for (index, value) in registers.genral.iter().enumerate() {
+            write!(&mut res, "V{:X}={:02X} ", index, value).unwrap();
+        }
+        writeln!(&mut res).unwrap();
+        writeln!(
+            &mut res,
+            "I={:03X} PC={:03X} SP={} DT={} ST={}",
+            registers.memory_address,
+            registers.program_counter,
+            registers.stack_pointer,
+            state.timers.delay_timer,
+            state.timers.sound_timer
+        )
+        .unwrap();
+        writeln!(&mut res, "State hash {:016X}", state.hash()).unwrap();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY_SIZE: usize = 4096;
+
+    fn registers() -> Chip8Registers {
+        let mut registers = Chip8Registers::new();
+        registers.program_counter = 0x200;
+        registers
+    }
+
+    #[test]
+    fn parse_keys_reads_frames_and_key_maps() {
+        let keys =
+            Chip8Batch::parse_keys("# frame keys\n60 5\n\n64 -  # release\n120 4 6\n130 f A\n")
+                .unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                (60, 1 << 5),
+                (64, 0),
+                (120, (1 << 4) | (1 << 6)),
+                (130, (1 << 0xF) | (1 << 0xA))
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keys_rejects_bad_lines() {
+        assert!(Chip8Batch::parse_keys("60 5\n10 4\n")
+            .unwrap_err()
+            .contains("line 2"));
+        assert!(Chip8Batch::parse_keys("sixty 5\n").is_err());
+        assert!(Chip8Batch::parse_keys("60 10\n").is_err());
+        assert!(Chip8Batch::parse_keys("60 g\n").is_err());
+    }
+
+    #[test]
+    fn unknown_instructions_are_faults() {
+        let registers = registers();
+        for instruction in [0xE000, 0x8008, 0x800F, 0xF0FF, 0xE19F] {
+            let fault = Chip8Batch::fault(instruction, &registers, MEMORY_SIZE);
+            assert!(
+                fault.unwrap().contains("unknown instruction"),
+                "{:04X}",
+                instruction
+            );
+        }
+        for instruction in [0x00E0, 0x6001, 0x800E, 0xE09E, 0xE0A1, 0xF00A, 0xF165] {
+            assert_eq!(
+                Chip8Batch::fault(instruction, &registers, MEMORY_SIZE),
+                None,
+                "{:04X}",
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn stack_faults() {
+        let mut registers = registers();
+        assert!(Chip8Batch::fault(0x00EE, &registers, MEMORY_SIZE)
+            .unwrap()
+            .contains("underflow"));
+        registers.stack_pointer = registers.stack.len() as u8;
+        assert!(Chip8Batch::fault(0x2300, &registers, MEMORY_SIZE)
+            .unwrap()
+            .contains("overflow"));
+        assert_eq!(Chip8Batch::fault(0x00EE, &registers, MEMORY_SIZE), None);
+    }
+
+    #[test]
+    fn reads_past_the_end_of_memory_are_faults() {
+        let mut registers = registers();
+        registers.memory_address = 0xFFD;
+        assert_eq!(Chip8Batch::fault(0xF033, &registers, MEMORY_SIZE), None);
+        assert!(Chip8Batch::fault(0xF355, &registers, MEMORY_SIZE).is_some());
+        assert!(Chip8Batch::fault(0xD014, &registers, MEMORY_SIZE).is_some());
+        registers.memory_address = 0xFFE;
+        assert!(Chip8Batch::fault(0xF033, &registers, MEMORY_SIZE).is_some());
+    }
+}
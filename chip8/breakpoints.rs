@@ -0,0 +1,30 @@
+use std::collections::BTreeSet;
+
+#[derive(Debug)]
+pub struct Chip8Breakpoints {
+    addresses: BTreeSet<u16>,
+
+    // Address of the last breakpoint that stopped the cpu thread
+    pub hit: Option<u16>,
+}
+
+impl Chip8Breakpoints {
+    pub fn new() -> Chip8Breakpoints {
+        Chip8Breakpoints {
+            addresses: BTreeSet::new(),
+            hit: None,
+        }
+    }
+
+    pub fn insert(&mut self, address: u16) {
+        self.addresses.insert(address);
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.addresses.contains(&address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &u16> {
+        self.addresses.iter()
+    }
+}
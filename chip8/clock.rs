@@ -0,0 +1,137 @@
+use super::config::{Chip8Config, Chip8Timing};
+use super::vip_timing::Chip8VipTiming;
+
+use std::time::Duration;
+
+//How fast emulated time runs compared to real time, the cpu and the timers speed up and slow down together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Speed {
+    // Percent of the normal frame rate
+    Percent(u32),
+
+    // Frames back to back as fast as the host manages
+    Turbo,
+}
+
+impl Chip8Speed {
+    pub const NORMAL: Chip8Speed = Chip8Speed::Percent(100);
+
+    // Steps taken by faster and slower, faster goes from the last one to turbo
+    const STEPS: [u32; 8] = [10, 25, 50, 100, 150, 200, 400, 800];
+
+    //A multiplier like 0.5 or 2, or turbo
+    pub fn from_name(name: &str) -> Option<Chip8Speed> {
+        if name == "turbo" {
+            return Some(Chip8Speed::Turbo);
+        }
+        let factor = name.trim_end_matches('x').parse::<f64>().ok()?;
+        let percent = (factor * 100.0).round();
+        if !(10.0..=10_000.0).contains(&percent) {
+            return None;
+        }
+        Some(Chip8Speed::Percent(percent as u32))
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Chip8Speed::Percent(percent) if percent % 100 == 0 => format!("{}x", percent / 100),
+            Chip8Speed::Percent(percent) => format!("{}x", percent as f64 / 100.0),
+            Chip8Speed::Turbo => "turbo".to_string(),
+        }
+    }
+
+    pub fn faster(self) -> Chip8Speed {
+        match self {
+            Chip8Speed::Percent(percent) => Chip8Speed::STEPS
+                .iter()
+                .find(|step| **step > percent)
+                .map_or(Chip8Speed::Turbo, |step| Chip8Speed::Percent(*step)),
+            Chip8Speed::Turbo => Chip8Speed::Turbo,
+        }
+    }
+
+    pub fn slower(self) -> Chip8Speed {
+        let percent = match self {
+            Chip8Speed::Percent(percent) => percent,
+            Chip8Speed::Turbo => u32::MAX,
+        };
+        Chip8Speed::STEPS
+            .iter()
+            .rev()
+            .find(|step| **step < percent)
+            .map_or(Chip8Speed::Percent(Chip8Speed::STEPS[0]), |step| {
+                Chip8Speed::Percent(*step)
+            })
+    }
+}
+
+//Master clock shared by the scheduler task and headless runs, every frame runs the cpu cycles
+//owed for cpu_hz and then ticks the timers exactly once
+#[derive(Debug, Clone)]
+pub struct Chip8Clock {
+    // Cycles carried to the next frame when cpu_hz isn't a multiple of the frame rate
+    carry: u32,
+
+    // VIP machine cycles the last frame went over its budget, taken from the next one
+    debt: u32,
+
+    // Used and available in the last frame, instructions or VIP machine cycles
+    pub used: u32,
+    pub budget: u32,
+
+    // Kept through resets, it belongs to the session rather than the machine
+    pub speed: Chip8Speed,
+}
+
+impl Chip8Clock {
+    // How far the scheduler may fall behind before it gives up on catching up and drops frames
+    pub const MAX_CATCH_UP_FRAMES: u64 = 6;
+
+    // How long turbo runs frames before it lets the scheduler look at its messages
+    pub const TURBO_SLICE: Duration = Duration::from_millis(10);
+
+    pub fn new() -> Chip8Clock {
+        Chip8Clock {
+            carry: 0,
+            debt: 0,
+            used: 0,
+            budget: 0,
+            speed: Chip8Speed::NORMAL,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.carry = 0;
+        self.debt = 0;
+    }
+
+    //What the cpu may spend in the next frame, instructions or VIP machine cycles depending on the timing
+    pub fn frame_budget(&mut self, config: &Chip8Config) -> u32 {
+        match config.timing {
+            Chip8Timing::Instructions => self.cycles(config.cpu_hz, config.timer_hz),
+            Chip8Timing::Vip => Chip8VipTiming::BUDGET.saturating_sub(self.debt),
+        }
+    }
+
+    //An instruction that doesn't fit in the rest of a VIP frame still runs, what it overran is owed by the next frame
+    pub fn end_frame(&mut self, used: u32, budget: u32) {
+        self.debt = used.saturating_sub(budget);
+        self.used = used;
+        self.budget = budget;
+    }
+
+    //Whole cycles for the next frame, over a second they add up to exactly cpu_hz
+    fn cycles(&mut self, cpu_hz: u32, frame_hz: u32) -> u32 {
+        let frame_hz = frame_hz.max(1);
+        let total = cpu_hz + self.carry;
+        self.carry = total % frame_hz;
+        total / frame_hz
+    }
+
+    //Time from the start of the schedule to the start of frame at percent of the normal speed, computed
+    //from the frame count so rounding never adds up into drift
+    pub fn frame_offset(frame: u64, frame_hz: u32, percent: u32) -> Duration {
+        let rate = frame_hz.max(1) as u128 * percent.max(1) as u128;
+        Duration::from_nanos((frame as u128 * 100_000_000_000 / rate) as u64)
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip8Quirks {
+    // 8xy6/8xyE shift Vy and store the result in Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    // Fx55/Fx65 leave I pointing after the last register that was stored/loaded
+    pub load_store_increments_i: bool,
+
+    // Bnnn jumps to nnn + Vx (x being the highest nibble of nnn) instead of nnn + V0
+    pub jump_uses_vx: bool,
+
+    // 8xy1/8xy2/8xy3 reset VF to 0
+    pub logic_resets_vf: bool,
+
+    // Sprites are clipped at the edge of the screen instead of wrapping around
+    pub clip_sprites: bool,
+
+    // Dxyn waits for the next 60Hz tick like the COSMAC VIP waiting for vertical blank, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chip8Timing {
+    // Every instruction takes the same time, cpu_hz of them a second
+    Instructions,
+
+    // Instructions cost what they took on the COSMAC VIP out of a fixed budget per frame, cpu_hz is ignored
+    Vip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip8Config {
+    pub quirks: Chip8Quirks,
+
+    // Instructions executed per second by the cpu thread
+    pub cpu_hz: u32,
+
+    // How the instructions of a frame are counted, kept in movies but not in save states
+    pub timing: Chip8Timing,
+
+    // Delay/sound timer ticks per second, only paces the timer thread so it isn't saved in states or movies
+    pub timer_hz: u32,
+}
+
+impl Chip8Quirks {
+    pub fn new() -> Chip8Quirks {
+        Chip8Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.shift_uses_vy as u8)
+            | (self.load_store_increments_i as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.logic_resets_vf as u8) << 3
+            | (self.clip_sprites as u8) << 4
+            | (self.display_wait as u8) << 5
+    }
+
+    pub fn from_bits(bits: u8) -> Chip8Quirks {
+        Chip8Quirks {
+            shift_uses_vy: bits & 1 > 0,
+            load_store_increments_i: bits & (1 << 1) > 0,
+            jump_uses_vx: bits & (1 << 2) > 0,
+            logic_resets_vf: bits & (1 << 3) > 0,
+            clip_sprites: bits & (1 << 4) > 0,
+            display_wait: bits & (1 << 5) > 0,
+        }
+    }
+}
+
+impl Chip8Timing {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Chip8Timing::Instructions => 0,
+            Chip8Timing::Vip => 1,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Chip8Timing> {
+        match value {
+            0 => Some(Chip8Timing::Instructions),
+            1 => Some(Chip8Timing::Vip),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Chip8Timing> {
+        match name {
+            "instructions" => Some(Chip8Timing::Instructions),
+            "vip" => Some(Chip8Timing::Vip),
+            _ => None,
+        }
+    }
+}
+
+impl Chip8Config {
+    pub const DEFAULT_CPU_HZ: u32 = 500;
+    pub const DEFAULT_TIMER_HZ: u32 = 60;
+
+    pub fn new() -> Chip8Config {
+        Chip8Config {
+            quirks: Chip8Quirks::new(),
+            cpu_hz: Chip8Config::DEFAULT_CPU_HZ,
+            timing: Chip8Timing::Instructions,
+            timer_hz: Chip8Config::DEFAULT_TIMER_HZ,
+        }
+    }
+}
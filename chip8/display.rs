@@ -1,37 +1,31 @@
 use std::fmt::Write;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8Display {
     screen_buffer_array: [[u8; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT],
+
+    // Bumped on every change to the buffer, row_generations holds the generation each row last changed at
+    generation: u64,
+    row_generations: [u64; Chip8Display::HEIGHT],
 }
 
 impl std::fmt::Display for Chip8Display {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        //let tmp = self.raw_array[0][0].to_string();
-        let result =
-            self.screen_buffer_array
-                .iter()
-                .enumerate()
-                .fold(String::new(), |mut acc_y, y| {
-                    write!(
-                        &mut acc_y,
-                        "{}\n",
-                        y.1.iter().enumerate().fold(String::new(), |mut acc_x, x| {
-                            for i in 0..8 {
-                                let bit_mask = 1 << i;
-                                let bit = bit_mask & x.1 > 0;
-                                if bit {
-                                    write!(&mut acc_x, "⬜").unwrap();
-                                } else {
-                                    write!(&mut acc_x, "⬛").unwrap();
-                                }
-                            }
-                            acc_x
-                        })
+        let result = self
+            .screen_buffer_array
+            .iter()
+            .fold(String::new(), |mut acc_y, row| {
+                writeln!(
+                    &mut acc_y,
+                    "{}",
+                    Chip8Display::render_row(
+                        row.iter()
+                            .flat_map(|x| (0..8).map(move |i| (1 << i) & x > 0))
                     )
-                    .unwrap();
-                    acc_y
-                });
+                )
+                .unwrap();
+                acc_y
+            });
 
         f.write_fmt(format_args!("{}", result))
     }
@@ -41,12 +35,29 @@ impl Chip8Display {
     pub const WIDTH: usize = 64;
     pub const HEIGHT: usize = 32;
 
+    pub const PIXEL_ON: &'static str = "⬜";
+    pub const PIXEL_OFF: &'static str = "⬛";
+
     pub fn new() -> Chip8Display {
         Chip8Display {
             screen_buffer_array: [[0; 8]; 32],
+            generation: 0,
+            row_generations: [0; Chip8Display::HEIGHT],
         }
     }
 
+    //Render a row of pixels (left to right) as text, used for the Display impl and sprite dumps
+    pub fn render_row<I: IntoIterator<Item = bool>>(pixels: I) -> String {
+        pixels.into_iter().fold(String::new(), |mut acc, bit| {
+            if bit {
+                acc.push_str(Chip8Display::PIXEL_ON);
+            } else {
+                acc.push_str(Chip8Display::PIXEL_OFF);
+            }
+            acc
+        })
+    }
+
     //TODO Make set pixel return the XOR result and remove get/unset pixel
     pub fn set_pixel(&mut self, x: usize, y: usize) {
         let w_x = x % Chip8Display::WIDTH;
@@ -55,8 +66,10 @@ impl Chip8Display {
         let sub_pixel_x = w_x / 8;
         let pixel = 128 >> (7 - (w_x % 8));
 
-        self.screen_buffer_array[w_y][sub_pixel_x] =
-            self.screen_buffer_array[w_y][sub_pixel_x] | pixel;
+        if self.screen_buffer_array[w_y][sub_pixel_x] & pixel == 0 {
+            self.screen_buffer_array[w_y][sub_pixel_x] |= pixel;
+            self.mark_dirty(w_y);
+        }
     }
 
     pub fn get_pixel(&self, x: usize, y: usize) -> bool {
@@ -76,33 +89,47 @@ impl Chip8Display {
         let sub_pixel_x = w_x / 8;
         let pixel = 128 >> (7 - (w_x % 8));
 
-        self.screen_buffer_array[w_y][sub_pixel_x] =
-            self.screen_buffer_array[w_y][sub_pixel_x] & !pixel;
+        if self.screen_buffer_array[w_y][sub_pixel_x] & pixel != 0 {
+            self.screen_buffer_array[w_y][sub_pixel_x] &= !pixel;
+            self.mark_dirty(w_y);
+        }
     }
 
     pub fn clear(&mut self) {
-        for data_row in self.screen_buffer_array.iter_mut() {
-            for data in data_row.iter_mut() {
-                *data = 0;
-            }
-        }
+        self.set_raw_rows([[0; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT]);
+    }
+
+    fn mark_dirty(&mut self, y: usize) {
+        self.generation += 1;
+        self.row_generations[y] = self.generation;
+    }
+
+    //Changes whenever the buffer does, a renderer that saw this generation can skip the frame
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    //Rows that changed after generation since, so only those need to be redrawn
+    pub fn dirty_rows(&self, since: u64) -> impl Iterator<Item = usize> + '_ {
+        self.row_generations
+            .iter()
+            .enumerate()
+            .filter(move |(_, generation)| **generation > since)
+            .map(|(y, _)| y)
+    }
+
+    //Raw rows of the screen buffer, bit n of byte b in a row is pixel x = b * 8 + n
+    pub fn get_raw_rows(&self) -> &[[u8; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT] {
+        &self.screen_buffer_array
     }
 
-    pub fn get_set_pixel_coords(&self) -> Vec<(f64, f64)> {
-        let mut res: Vec<(f64, f64)> = Vec::new();
-        for (y, data) in self.screen_buffer_array.iter().enumerate() {
-            for (x_, x_byte) in data.iter().enumerate() {
-                if *x_byte > 0 {
-                    for i in 0..8 {
-                        let bit_mask = 1 << i;
-                        let bit = bit_mask & *x_byte > 0;
-                        if bit {
-                            res.push(((x_ * 8 + i) as f64, (Chip8Display::HEIGHT - y) as f64));
-                        }
-                    }
-                }
+    //Only rows that differ are marked dirty
+    pub fn set_raw_rows(&mut self, rows: [[u8; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT]) {
+        for (y, row) in rows.iter().enumerate() {
+            if self.screen_buffer_array[y] != *row {
+                self.screen_buffer_array[y] = *row;
+                self.mark_dirty(y);
             }
         }
-        res
     }
 }
@@ -0,0 +1,79 @@
+use super::display::Chip8Display;
+
+use std::borrow::Cow;
+use std::io;
+
+type Rows = [[u8; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT];
+
+#[derive(Debug)]
+pub struct Chip8GifRecorder {
+    // Each distinct screen with the number of 60Hz frames it stayed on screen
+    frames: Vec<(Rows, u32)>,
+}
+
+impl Chip8GifRecorder {
+    pub fn new() -> Chip8GifRecorder {
+        Chip8GifRecorder { frames: Vec::new() }
+    }
+
+    //Called once per 60Hz frame, a screen identical to the previous one only extends its duration
+    pub fn capture(&mut self, display: &Chip8Display) {
+        let rows = display.get_raw_rows();
+        match self.frames.last_mut() {
+            Some((last, count)) if last == rows => *count += 1,
+            _ => self.frames.push((*rows, 1)),
+        }
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frames.iter().map(|(_, count)| count).sum()
+    }
+
+    //planes holds the colour for no plane, the first, the second and both planes lit
+    pub fn encode(&self, scale: usize, planes: [(u8, u8, u8); 4]) -> io::Result<Vec<u8>> {
+        let scale = scale.max(1);
+        let width = (Chip8Display::WIDTH * scale) as u16;
+        let height = (Chip8Display::HEIGHT * scale) as u16;
+        let palette = planes
+            .iter()
+            .flat_map(|(r, g, b)| [*r, *g, *b])
+            .collect::<Vec<u8>>();
+
+        let mut res = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut res, width, height, &palette).map_err(io::Error::other)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(io::Error::other)?;
+
+            // GIF delays are in 1/100s, carry the rounding over so the total length stays right
+            let mut elapsed_frames = 0u32;
+            let mut elapsed_cs = 0u32;
+            for (rows, count) in self.frames.iter() {
+                elapsed_frames += count;
+                let end_cs = elapsed_frames * 100 / 60;
+                let delay = (end_cs - elapsed_cs).max(1);
+                elapsed_cs += delay;
+
+                let mut pixels = Vec::with_capacity(width as usize * height as usize);
+                for y in 0..height as usize {
+                    let row = &rows[y / scale];
+                    for x in 0..width as usize {
+                        let x = x / scale;
+                        pixels.push((row[x / 8] >> (x % 8)) & 1);
+                    }
+                }
+                let frame = gif::Frame {
+                    width,
+                    height,
+                    delay: delay as u16,
+                    buffer: Cow::Owned(pixels),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).map_err(io::Error::other)?;
+            }
+        }
+        Ok(res)
+    }
+}
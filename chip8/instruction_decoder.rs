@@ -1,3 +1,9 @@
+use super::symbols::Chip8Symbols;
+
+use super::rom_loader::{self, Chip8RomFormat};
+
+use std::io;
+
 #[derive(Debug)]
 pub struct Chip8InstructionDecoder {
     decoded_instructions: Vec<String>,
@@ -10,11 +16,15 @@ impl Chip8InstructionDecoder {
         }
     }
 
-    fn decode_file(&mut self, filename: &str) {
-        let contents = fs::read(filename).expect("Something went wrong reading the file");
+    pub fn decode_file(
+        &mut self,
+        filename: &str,
+        format: Option<Chip8RomFormat>,
+        symbols: &Chip8Symbols,
+    ) -> io::Result<()> {
+        let contents = rom_loader::read(filename, format)?;
         let contents: Vec<u16> = contents
             .chunks_exact(2)
-            .into_iter()
             .map(|a| u16::from_ne_bytes([a[1], a[0]]))
             .collect();
 
@@ -27,20 +37,20 @@ impl Chip8InstructionDecoder {
                     0x00E0 => {
                         //00E0 - CLS
                         //Clear the display.
-                        self.decoded_instructions.push(format!("CLS"));
+                        self.decoded_instructions.push("CLS".to_string());
                     }
                     0x00EE => {
                         //00EE - RET
                         //Return from a subroutine.
-                        self.decoded_instructions.push(format!("RET"));
+                        self.decoded_instructions.push("RET".to_string());
                     }
                     0x0000..=0x0FFF => {
                         let addr = data & 0x0FFF;
                         self.decoded_instructions
-                            .push(format!("SYS addr {:#05X}", addr));
+                            .push(format!("SYS addr {}", symbols.format_address(addr)));
                     }
                     _ => {
-                        self.decoded_instructions.push(format!("SYS UNKNOWN"));
+                        self.decoded_instructions.push("SYS UNKNOWN".to_string());
                     }
                 },
                 0x1 => {
@@ -48,7 +58,7 @@ impl Chip8InstructionDecoder {
                     // Jump to location nnn.
                     // The interpreter sets the program counter to nnn.
                     let addr = data & 0x0FFF;
-                    let res = format!("JP addr {:#05X}", addr);
+                    let res = format!("JP addr {}", symbols.format_address(addr));
                     self.decoded_instructions.push(res);
                 }
                 0x2 => {
@@ -56,7 +66,7 @@ impl Chip8InstructionDecoder {
                     // Call subroutine at nnn.
                     // The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
                     let addr = data & 0x0FFF;
-                    let res = format!("CALL addr {:#05X}", addr);
+                    let res = format!("CALL addr {}", symbols.format_address(addr));
                     self.decoded_instructions.push(res);
                 }
                 0x3 => {
@@ -207,7 +217,7 @@ impl Chip8InstructionDecoder {
                     // Set I = nnn.
                     // The value of register I is set to nnn.
                     let addr = data & 0x0FFF;
-                    let res = format!("LD I, addr {:#05X}", addr);
+                    let res = format!("LD I, addr {}", symbols.format_address(addr));
                     self.decoded_instructions.push(res);
                 }
                 0xB => {
@@ -215,7 +225,7 @@ impl Chip8InstructionDecoder {
                     // Jump to location nnn + V0.
                     // The program counter is set to nnn plus the value of V0.
                     let addr = data & 0x0FFF;
-                    let res = format!("JP V0, addr {:#05X}", addr);
+                    let res = format!("JP V0, addr {}", symbols.format_address(addr));
                     self.decoded_instructions.push(res);
                 }
                 0xC => {
@@ -346,14 +356,18 @@ impl Chip8InstructionDecoder {
                 }
             }
         }
+        Ok(())
     }
 
-    fn print_loaded_inst(self) {
+    pub fn print_loaded_inst(self, symbols: &Chip8Symbols) {
         // 0x200 is start or program / data block
         let mut c = 0x200;
         for i in self.decoded_instructions {
+            if let Some(name) = symbols.label(c) {
+                println!("{}:", name);
+            }
             println!("{:#05X}: {}", c, i);
-            c = c + 2;
+            c += 2;
         }
     }
 }
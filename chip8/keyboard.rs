@@ -1,21 +1,37 @@
-#[derive(Debug)]
+//Keys pressed in the UI go to the pending key map and only reach the key map the cpu reads at a frame
+//boundary (apply_pending), so every instruction of a frame sees the same keys. Movies record key maps per
+//frame, with input landing mid-frame a replay could not reproduce what the cpu saw
+#[derive(Debug, Clone)]
 pub struct Chip8Keyboard {
     key_map: u16,
+    pending_key_map: u16,
 }
 
 impl Chip8Keyboard {
     pub fn new() -> Chip8Keyboard {
-        Chip8Keyboard { key_map: 0 }
+        Chip8Keyboard {
+            key_map: 0,
+            pending_key_map: 0,
+        }
     }
 
     pub fn reset_keys(&mut self) {
         self.key_map = 0;
+        self.pending_key_map = 0;
     }
 
-    pub fn set_key(&mut self, key: u8) {
+    pub fn set_pending_key(&mut self, key: u8) {
         let byte = key & 0x0F;
         let bit_mask = 1 << byte;
-        self.key_map = self.key_map | bit_mask;
+        self.pending_key_map |= bit_mask;
+    }
+
+    pub fn reset_pending_keys(&mut self) {
+        self.pending_key_map = 0;
+    }
+
+    pub fn apply_pending(&mut self) {
+        self.key_map = self.pending_key_map;
     }
 
     pub fn get_key(&self, key: u8) -> bool {
@@ -27,4 +43,10 @@ impl Chip8Keyboard {
     pub fn get_key_map(&self) -> u16 {
         self.key_map
     }
+
+    //Key maps from movies, key scripts and save states take effect at once and stay down
+    pub fn set_key_map(&mut self, key_map: u16) {
+        self.key_map = key_map;
+        self.pending_key_map = key_map;
+    }
 }
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8Memory {
     pub raw_array: [u8; 4096],
 }
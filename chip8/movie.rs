@@ -0,0 +1,124 @@
+// Movie file format, all numbers are little endian.
+//
+// Header (43 bytes)
+//   0  4  magic "C8MV"
+//   4  2  format version
+//   6  8  rng seed
+//   14 1  quirks, see Chip8Quirks::to_bits
+//   15 4  cpu speed in instructions per second
+//   19 8  length of the movie in frames
+//   27 8  state hash after the last frame, see Chip8State::hash
+//   35 4  number of input events
+//   39 1  timing, see Chip8Timing
+//   40 3  reserved
+//
+// Input events (10 bytes each, ordered by frame)
+//   0  8  frame the key map applies from
+//   8  2  key map, bit n set when key n is down
+use super::config::{Chip8Config, Chip8Quirks, Chip8Timing};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Cursor, Read, Write};
+
+#[derive(Debug, Clone)]
+pub struct Chip8Movie {
+    pub seed: u64,
+    pub config: Chip8Config,
+    pub frames: u64,
+    pub final_hash: u64,
+    events: Vec<(u64, u16)>,
+}
+
+impl Chip8Movie {
+    pub const MAGIC: &'static [u8; 4] = b"C8MV";
+    pub const VERSION: u16 = 1;
+
+    pub fn new(seed: u64, config: Chip8Config) -> Chip8Movie {
+        Chip8Movie {
+            seed,
+            config,
+            frames: 0,
+            final_hash: 0,
+            events: Vec::new(),
+        }
+    }
+
+    //Called once per frame while recording, only changes to the keypad are stored
+    pub fn record(&mut self, frame: u64, key_map: u16) {
+        if self.events.last().map(|(_, last)| *last) != Some(key_map) {
+            self.events.push((frame, key_map));
+        }
+        self.frames = self.frames.max(frame);
+    }
+
+    //Key map that takes effect at frame, None when the keypad doesn't change on that frame
+    pub fn key_map_at(&self, frame: u64) -> Option<u16> {
+        self.events
+            .binary_search_by_key(&frame, |(event_frame, _)| *event_frame)
+            .ok()
+            .map(|index| self.events[index].1)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::new();
+        res.extend_from_slice(Chip8Movie::MAGIC);
+        res.write_u16::<LittleEndian>(Chip8Movie::VERSION).unwrap();
+        res.write_u64::<LittleEndian>(self.seed).unwrap();
+        res.write_u8(self.config.quirks.to_bits()).unwrap();
+        res.write_u32::<LittleEndian>(self.config.cpu_hz).unwrap();
+        res.write_u64::<LittleEndian>(self.frames).unwrap();
+        res.write_u64::<LittleEndian>(self.final_hash).unwrap();
+        res.write_u32::<LittleEndian>(self.events.len() as u32)
+            .unwrap();
+        res.write_u8(self.config.timing.to_u8()).unwrap();
+        res.write_all(&[0; 3]).unwrap();
+        for (frame, key_map) in self.events.iter() {
+            res.write_u64::<LittleEndian>(*frame).unwrap();
+            res.write_u16::<LittleEndian>(*key_map).unwrap();
+        }
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Chip8Movie> {
+        let mut reader = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != Chip8Movie::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a chip8 movie",
+            ));
+        }
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version != Chip8Movie::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported movie version {}", version),
+            ));
+        }
+        let seed = reader.read_u64::<LittleEndian>()?;
+        let mut config = Chip8Config::new();
+        config.quirks = Chip8Quirks::from_bits(reader.read_u8()?);
+        config.cpu_hz = reader.read_u32::<LittleEndian>()?;
+        let frames = reader.read_u64::<LittleEndian>()?;
+        let final_hash = reader.read_u64::<LittleEndian>()?;
+        let event_count = reader.read_u32::<LittleEndian>()?;
+        config.timing = Chip8Timing::from_u8(reader.read_u8()?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown timing in movie"))?;
+        let mut _reserved = [0; 3];
+        reader.read_exact(&mut _reserved)?;
+        let mut events = Vec::with_capacity(event_count as usize);
+        for _ in 0..event_count {
+            let frame = reader.read_u64::<LittleEndian>()?;
+            let key_map = reader.read_u16::<LittleEndian>()?;
+            events.push((frame, key_map));
+        }
+        Ok(Chip8Movie {
+            seed,
+            config,
+            frames,
+            final_hash,
+            events,
+        })
+    }
+}
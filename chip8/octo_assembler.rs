@@ -0,0 +1,1006 @@
+// Assembler for Octo source, the language Octo cartridges carry their program in. It follows the Octo manual:
+//
+//   : name / :next name     labels, forward references are patched once the whole program is read
+//   :const :alias :calc     constants, register aliases and constant expressions ({ } with no operator
+//                           precedence, evaluated right to left like Octo)
+//   :macro name args { }    macros, expanded by substituting the arguments into the body
+//   :org :byte :unpack      placing code and data
+//   clear return ; jump jump0 native sprite save load bcd hex bighex random key delay buzzer and the
+//   := += -= =- |= &= ^= >>= <<= forms, if ... then, if ... begin ... else ... end and loop ... while ... again
+//
+// SCHIP and XO-CHIP instructions are assembled too even though the emulator only runs plain CHIP-8.
+// :breakpoint and :monitor are accepted and ignored, :stringmode is not supported.
+// Like Octo the program starts with a jump to main at 0x200.
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    //Low 12 bits of the instruction at the address
+    Address,
+    //A whole byte
+    Byte,
+    //Two bytes, big endian
+    Long,
+    //High nibble of the address in the low nibble of the byte
+    HighNibble,
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    name: String,
+    line: usize,
+}
+
+enum Block {
+    //Address of the jump over the block, patched by else or end
+    Begin(usize),
+    Else(usize),
+    //Start of the loop and the jumps out of it made by while
+    Loop(usize, Vec<usize>),
+}
+
+pub struct Chip8OctoAssembler {
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    line: usize,
+    values: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Chip8OctoAssembler {
+    const PROGRAM_START_ADDRESS: usize = 0x200;
+    const MEMORY_SIZE: usize = 0x10000;
+
+    pub fn new(source: &str) -> Chip8OctoAssembler {
+        let mut aliases = HashMap::new();
+        aliases.insert("compare-temp".to_string(), 0xF);
+        aliases.insert("unpack-hi".to_string(), 0x0);
+        aliases.insert("unpack-lo".to_string(), 0x1);
+        Chip8OctoAssembler {
+            tokens: Chip8OctoAssembler::tokenize(source),
+            memory: vec![0; Chip8OctoAssembler::MEMORY_SIZE],
+            here: Chip8OctoAssembler::PROGRAM_START_ADDRESS + 2,
+            end: Chip8OctoAssembler::PROGRAM_START_ADDRESS + 2,
+            line: 1,
+            values: HashMap::new(),
+            aliases,
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    //Bytes to load at 0x200
+    pub fn assemble(source: &str) -> io::Result<Vec<u8>> {
+        let mut assembler = Chip8OctoAssembler::new(source);
+        while !assembler.tokens.is_empty() {
+            assembler.statement()?;
+        }
+        assembler.finish()
+    }
+
+    //Whitespace separated tokens, # starts a comment and "..." is one token
+    fn tokenize(source: &str) -> VecDeque<Token> {
+        let mut res = VecDeque::new();
+        for (index, line) in source.lines().enumerate() {
+            let mut chars = line.chars().peekable();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    chars.next();
+                } else if c == '#' {
+                    break;
+                } else if c == '"' {
+                    let mut text = String::new();
+                    text.push(chars.next().unwrap());
+                    for c in chars.by_ref() {
+                        text.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                    res.push_back(Token {
+                        text,
+                        line: index + 1,
+                    });
+                } else {
+                    let mut text = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+                        text.push(c);
+                        chars.next();
+                    }
+                    res.push_back(Token {
+                        text,
+                        line: index + 1,
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    fn error<T>(&self, msg: String) -> io::Result<T> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Octo source line {}: {}", self.line, msg),
+        ))
+    }
+
+    fn next(&mut self) -> io::Result<String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of program".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> io::Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn parse_number(token: &str) -> Option<i64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok()
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse::<i64>().ok()
+        } else {
+            None
+        }?;
+        Some(if negative { -value } else { value })
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(token) {
+            return Some(*register);
+        }
+        let digit = token.strip_prefix('v').or(token.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn next_register(&mut self) -> io::Result<u8> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    fn is_name(token: &str) -> bool {
+        !token.is_empty()
+            && !token.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == ':')
+            && token != "{"
+            && token != "}"
+    }
+
+    //A number or a name defined so far, names not defined yet are returned for patching later
+    fn value(&mut self) -> io::Result<Result<i64, String>> {
+        let token = self.next()?;
+        if let Some(value) = Chip8OctoAssembler::parse_number(&token) {
+            return Ok(Ok(value));
+        }
+        if let Some(value) = self.values.get(&token) {
+            return Ok(Ok(*value));
+        }
+        if Chip8OctoAssembler::is_name(&token) && self.register(&token).is_none() {
+            return Ok(Err(token));
+        }
+        self.error(format!("expected a number or a name, found '{}'", token))
+    }
+
+    fn known_value(&mut self) -> io::Result<i64> {
+        match self.value()? {
+            Ok(value) => Ok(value),
+            Err(name) => self.error(format!("'{}' must be defined before it is used here", name)),
+        }
+    }
+
+    fn byte_value(&mut self) -> io::Result<u8> {
+        let value = self.known_value()?;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> io::Result<()> {
+        if self.here >= Chip8OctoAssembler::MEMORY_SIZE {
+            return self.error("program is larger than 64K".to_string());
+        }
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: u16) -> io::Result<()> {
+        self.emit_byte((instruction >> 8) as u8)?;
+        self.emit_byte(instruction as u8)
+    }
+
+    //Instruction with a 12 bit address taken from the next token
+    fn emit_address(&mut self, opcode: u16) -> io::Result<()> {
+        let address = self.here;
+        match self.value()? {
+            Ok(value) => {
+                if !(0..=0xFFF).contains(&value) {
+                    return self.error(format!("address {:#X} does not fit in 12 bits", value));
+                }
+                self.emit(opcode | value as u16)
+            }
+            Err(name) => {
+                self.fixup(address, FixupKind::Address, name);
+                self.emit(opcode)
+            }
+        }
+    }
+
+    fn fixup(&mut self, address: usize, kind: FixupKind, name: String) {
+        self.fixups.push(Fixup {
+            address,
+            kind,
+            name,
+            line: self.line,
+        });
+    }
+
+    fn define(&mut self, name: String, value: i64) -> io::Result<()> {
+        if !Chip8OctoAssembler::is_name(&name) || self.register(&name).is_some() {
+            return self.error(format!("'{}' can't be used as a name", name));
+        }
+        if self.values.contains_key(&name) {
+            return self.error(format!("'{}' is already defined", name));
+        }
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> io::Result<()> {
+        let token = self.next()?;
+        if let Some(value) = Chip8OctoAssembler::parse_number(&token) {
+            if !(-128..=255).contains(&value) {
+                return self.error(format!("{} does not fit in a byte", value));
+            }
+            return self.emit_byte(value as u8);
+        }
+        if let Some(register) = self.register(&token) {
+            return self.assignment(register);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                let here = self.here as i64;
+                self.define(name, here)
+            }
+            ":next" => {
+                let name = self.next()?;
+                let here = self.here as i64 + 1;
+                self.define(name, here)
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.known_value()?;
+                self.define(name, value)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.next_register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.values.insert(name, value as i64);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":org" => {
+                let address = if self.peek() == Some("{") {
+                    self.calc()? as i64
+                } else {
+                    self.known_value()?
+                };
+                if !(Chip8OctoAssembler::PROGRAM_START_ADDRESS as i64
+                    ..Chip8OctoAssembler::MEMORY_SIZE as i64)
+                    .contains(&address)
+                {
+                    return self.error(format!("can't place code at {:#X}", address));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":byte" => {
+                if self.peek() == Some("{") {
+                    let value = self.calc()? as i64;
+                    return self.emit_byte(value as u8);
+                }
+                let address = self.here;
+                match self.value()? {
+                    Ok(value) => self.emit_byte(value as u8),
+                    Err(name) => {
+                        self.fixup(address, FixupKind::Byte, name);
+                        self.emit_byte(0)
+                    }
+                }
+            }
+            ":unpack" => self.unpack(),
+            ":call" => self.emit_address(0x2000),
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ":assert" => {
+                let message = if self.peek() != Some("{") {
+                    self.next()?
+                } else {
+                    "assertion failed".to_string()
+                };
+                if self.calc()? == 0.0 {
+                    return self.error(message.trim_matches('"').to_string());
+                }
+                Ok(())
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "hires" => self.emit(0x00FF),
+            "lores" => self.emit(0x00FE),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "exit" => self.emit(0x00FD),
+            "audio" => self.emit(0xF002),
+            "scroll-down" => {
+                let rows = self.byte_value()? & 0xF;
+                self.emit(0x00C0 | rows as u16)
+            }
+            "scroll-up" => {
+                let rows = self.byte_value()? & 0xF;
+                self.emit(0x00D0 | rows as u16)
+            }
+            "plane" => {
+                let plane = self.byte_value()? & 0xF;
+                self.emit(0xF001 | (plane as u16) << 8)
+            }
+            "jump" => self.emit_address(0x1000),
+            "jump0" => self.emit_address(0xB000),
+            "native" => self.emit_address(0x0000),
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let height = self.byte_value()? & 0xF;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | height as u16)
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.next_register()?;
+                    let opcode = if token == "save" { 0x5002 } else { 0x5003 };
+                    return self.emit(opcode | (x as u16) << 8 | (y as u16) << 4);
+                }
+                let opcode = if token == "save" { 0xF055 } else { 0xF065 };
+                self.emit(opcode | (x as u16) << 8)
+            }
+            "saveflags" | "loadflags" | "bcd" => {
+                let x = self.next_register()?;
+                let opcode = match token.as_str() {
+                    "saveflags" => 0xF075,
+                    "loadflags" => 0xF085,
+                    _ => 0xF033,
+                };
+                self.emit(opcode | (x as u16) << 8)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | (x as u16) << 8)
+            }
+            "i" => self.index_assignment(),
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin(jump)) => {
+                    let address = self.here;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump);
+                    self.blocks.push(Block::Else(address));
+                    Ok(())
+                }
+                _ => self.error("'else' without 'begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin(jump)) | Some(Block::Else(jump)) => {
+                    self.patch_jump(jump);
+                    Ok(())
+                }
+                _ => self.error("'end' without 'begin'".to_string()),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop(self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                let loop_index = self
+                    .blocks
+                    .iter()
+                    .rposition(|block| matches!(block, Block::Loop(..)));
+                let Some(loop_index) = loop_index else {
+                    return self.error("'while' outside a loop".to_string());
+                };
+                self.condition(true)?;
+                let address = self.here;
+                self.emit(0x1000)?;
+                if let Block::Loop(_, breaks) = &mut self.blocks[loop_index] {
+                    breaks.push(address);
+                }
+                Ok(())
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, breaks)) => {
+                    self.emit(0x1000 | start as u16 & 0xFFF)?;
+                    for jump in breaks {
+                        self.patch_jump(jump);
+                    }
+                    Ok(())
+                }
+                _ => self.error("'again' without 'loop'".to_string()),
+            },
+            ":stringmode" => self.error(":stringmode is not supported".to_string()),
+            _ => {
+                if let Some(definition) = self.macros.get(&token).cloned() {
+                    return self.expand_macro(definition);
+                }
+                if token.starts_with(':') || !Chip8OctoAssembler::is_name(&token) {
+                    return self.error(format!("unknown statement '{}'", token));
+                }
+                //A bare name calls a subroutine
+                self.tokens.push_front(Token {
+                    text: token,
+                    line: self.line,
+                });
+                self.emit_address(0x2000)
+            }
+        }
+    }
+
+    fn patch_jump(&mut self, jump: usize) {
+        let here = self.here;
+        self.memory[jump] = 0x10 | (here >> 8) as u8 & 0x0F;
+        self.memory[jump + 1] = here as u8;
+    }
+
+    fn assignment(&mut self, x: u8) -> io::Result<()> {
+        let op = self.next()?;
+        let x16 = (x as u16) << 8;
+        if op == ":=" {
+            match self.peek() {
+                Some("key") => {
+                    self.next()?;
+                    return self.emit(0xF00A | x16);
+                }
+                Some("delay") => {
+                    self.next()?;
+                    return self.emit(0xF007 | x16);
+                }
+                Some("random") => {
+                    self.next()?;
+                    let mask = self.byte_value()?;
+                    return self.emit(0xC000 | x16 | mask as u16);
+                }
+                _ => {}
+            }
+        }
+        if let Some(y) = self.peek().and_then(|token| self.register(token)) {
+            self.next()?;
+            let low = match op.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("unknown operator '{}'", op)),
+            };
+            return self.emit(0x8000 | x16 | (y as u16) << 4 | low);
+        }
+        let address = self.here;
+        let (value, name) = match self.value()? {
+            Ok(value) if (-128..=255).contains(&value) => (value as u8, None),
+            Ok(value) => return self.error(format!("{} does not fit in a byte", value)),
+            Err(name) => (0, Some(name)),
+        };
+        let instruction = match op.as_str() {
+            ":=" => 0x6000 | x16 | value as u16,
+            "+=" => 0x7000 | x16 | value as u16,
+            "-=" if name.is_none() => 0x7000 | x16 | value.wrapping_neg() as u16,
+            _ => return self.error(format!("'{}' needs a register on the right", op)),
+        };
+        if let Some(name) = name {
+            self.fixup(address + 1, FixupKind::Byte, name);
+        }
+        self.emit(instruction)
+    }
+
+    fn index_assignment(&mut self) -> io::Result<()> {
+        let op = self.next()?;
+        if op == "+=" {
+            let x = self.next_register()?;
+            return self.emit(0xF01E | (x as u16) << 8);
+        }
+        if op != ":=" {
+            return self.error(format!("unknown operator '{}' for i", op));
+        }
+        match self.peek() {
+            Some("hex") | Some("bighex") => {
+                let opcode = if self.next()? == "hex" {
+                    0xF029
+                } else {
+                    0xF030
+                };
+                let x = self.next_register()?;
+                self.emit(opcode | (x as u16) << 8)
+            }
+            Some("long") => {
+                self.next()?;
+                self.emit(0xF000)?;
+                let address = self.here;
+                match self.value()? {
+                    Ok(value) => self.emit(value as u16),
+                    Err(name) => {
+                        self.fixup(address, FixupKind::Long, name);
+                        self.emit(0)
+                    }
+                }
+            }
+            _ => self.emit_address(0xA000),
+        }
+    }
+
+    //Emits the skip for `if ... then`: the next instruction is skipped unless the condition holds.
+    //With negated the next instruction is skipped when the condition holds.
+    fn condition(&mut self, negated: bool) -> io::Result<()> {
+        let x = self.next_register()?;
+        let mut op = self.next()?;
+        if negated {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                "<=" => ">",
+                _ => return self.error(format!("unknown comparison '{}'", op)),
+            }
+            .to_string();
+        }
+        let x16 = (x as u16) << 8;
+        match op.as_str() {
+            "key" => return self.emit(0xE0A1 | x16),
+            "-key" => return self.emit(0xE09E | x16),
+            _ => {}
+        }
+        let rhs = match self.peek().and_then(|token| self.register(token)) {
+            Some(y) => {
+                self.next()?;
+                Err(y)
+            }
+            None => Ok(self.byte_value()?),
+        };
+        match (op.as_str(), rhs) {
+            ("==", Ok(byte)) => self.emit(0x4000 | x16 | byte as u16),
+            ("!=", Ok(byte)) => self.emit(0x3000 | x16 | byte as u16),
+            ("==", Err(y)) => self.emit(0x9000 | x16 | (y as u16) << 4),
+            ("!=", Err(y)) => self.emit(0x5000 | x16 | (y as u16) << 4),
+            ("<", _) | (">", _) | ("<=", _) | (">=", _) => {
+                //The right hand side goes into compare-temp, VF ends up 1 when rhs >= x (-=) or x >= rhs (=-)
+                let temp = (self.aliases["compare-temp"] as u16) << 8;
+                match rhs {
+                    Ok(byte) => self.emit(0x6000 | temp | byte as u16)?,
+                    Err(y) => self.emit(0x8000 | temp | (y as u16) << 4)?,
+                }
+                let subtract = if op == ">" || op == "<=" { 0x5 } else { 0x7 };
+                self.emit(0x8000 | temp | (x as u16) << 4 | subtract)?;
+                let skip_value = if op == "<" || op == ">" { 1 } else { 0 };
+                self.emit(0x3F00 | skip_value)
+            }
+            _ => self.error(format!("unknown comparison '{}'", op)),
+        }
+    }
+
+    fn if_statement(&mut self) -> io::Result<()> {
+        //Look ahead for then/begin so the condition can be emitted the right way round
+        let keyword = self
+            .tokens
+            .iter()
+            .take(4)
+            .find(|token| token.text == "then" || token.text == "begin")
+            .map(|token| token.text.clone());
+        match keyword.as_deref() {
+            Some("then") => {
+                self.condition(false)?;
+                self.expect("then")
+            }
+            Some("begin") => {
+                self.condition(true)?;
+                self.expect("begin")?;
+                self.blocks.push(Block::Begin(self.here));
+                self.emit(0x1000)
+            }
+            _ => self.error("'if' without 'then' or 'begin'".to_string()),
+        }
+    }
+
+    fn unpack(&mut self) -> io::Result<()> {
+        let high = self.aliases["unpack-hi"] as u16;
+        let low = self.aliases["unpack-lo"] as u16;
+        let nibble = if self.peek() == Some("long") {
+            self.next()?;
+            None
+        } else {
+            Some(self.byte_value()? & 0xF)
+        };
+        let address = self.here;
+        let (value, name) = match self.value()? {
+            Ok(value) => (value as u16, None),
+            Err(name) => (0, Some(name)),
+        };
+        let high_byte = match nibble {
+            Some(nibble) => (nibble as u16) << 4 | (value >> 8) & 0xF,
+            None => value >> 8,
+        };
+        self.emit(0x6000 | high << 8 | high_byte & 0xFF)?;
+        self.emit(0x6000 | low << 8 | value & 0xFF)?;
+        if let Some(name) = name {
+            let kind = if nibble.is_some() {
+                FixupKind::HighNibble
+            } else {
+                FixupKind::Byte
+            };
+            //Byte fixups of the high byte take the high byte of the value, see finish
+            self.fixup(address + 1, kind, format!("{}\u{0}high", name));
+            self.fixup(address + 3, FixupKind::Byte, name);
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> io::Result<()> {
+        let name = self.next()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+        let body = self.braced_tokens()?;
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    //Tokens up to the closing brace, the opening one already read
+    fn braced_tokens(&mut self) -> io::Result<Vec<Token>> {
+        let mut depth = 1;
+        let mut res = Vec::new();
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return self.error("missing '}'".to_string()),
+            };
+            if token.text == "{" {
+                depth += 1;
+            } else if token.text == "}" {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(res);
+                }
+            }
+            res.push(token);
+        }
+    }
+
+    fn expand_macro(&mut self, definition: Macro) -> io::Result<()> {
+        let mut bindings = HashMap::new();
+        for arg in &definition.args {
+            let value = self.next()?;
+            bindings.insert(arg.clone(), value);
+        }
+        let line = self.line;
+        for token in definition.body.iter().rev() {
+            let text = bindings
+                .get(&token.text)
+                .cloned()
+                .unwrap_or_else(|| token.text.clone());
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    //{ expression } as in :calc, operators share one precedence and are evaluated right to left
+    fn calc(&mut self) -> io::Result<f64> {
+        self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        let mut tokens: VecDeque<String> = tokens.into_iter().map(|token| token.text).collect();
+        let value = self.calc_expression(&mut tokens)?;
+        if let Some(token) = tokens.front() {
+            return self.error(format!("unexpected '{}' in expression", token));
+        }
+        Ok(value)
+    }
+
+    fn calc_expression(&self, tokens: &mut VecDeque<String>) -> io::Result<f64> {
+        let lhs = self.calc_term(tokens)?;
+        let Some(op) = tokens.front().cloned() else {
+            return Ok(lhs);
+        };
+        if op == ")" {
+            return Ok(lhs);
+        }
+        tokens.pop_front();
+        let rhs = self.calc_expression(tokens)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        Ok(match op.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "<" => (lhs < rhs) as i64 as f64,
+            ">" => (lhs > rhs) as i64 as f64,
+            "<=" => (lhs <= rhs) as i64 as f64,
+            ">=" => (lhs >= rhs) as i64 as f64,
+            "==" => (lhs == rhs) as i64 as f64,
+            "!=" => (lhs != rhs) as i64 as f64,
+            _ => return self.error(format!("unknown operator '{}' in expression", op)),
+        })
+    }
+
+    fn calc_term(&self, tokens: &mut VecDeque<String>) -> io::Result<f64> {
+        let Some(token) = tokens.pop_front() else {
+            return self.error("expression ends too early".to_string());
+        };
+        if token == "(" {
+            let value = self.calc_expression(tokens)?;
+            if tokens.pop_front().as_deref() != Some(")") {
+                return self.error("missing ')' in expression".to_string());
+            }
+            return Ok(value);
+        }
+        let unary = |f: fn(f64) -> f64, tokens: &mut VecDeque<String>| -> io::Result<f64> {
+            Ok(f(self.calc_term(tokens)?))
+        };
+        match token.as_str() {
+            "-" => unary(|v| -v, tokens),
+            "~" => unary(|v| !(v as i64) as f64, tokens),
+            "!" => unary(|v| (v == 0.0) as i64 as f64, tokens),
+            "sin" => unary(f64::sin, tokens),
+            "cos" => unary(f64::cos, tokens),
+            "tan" => unary(f64::tan, tokens),
+            "exp" => unary(f64::exp, tokens),
+            "log" => unary(f64::ln, tokens),
+            "abs" => unary(f64::abs, tokens),
+            "sqrt" => unary(f64::sqrt, tokens),
+            "sign" => unary(f64::signum, tokens),
+            "ceil" => unary(f64::ceil, tokens),
+            "floor" => unary(f64::floor, tokens),
+            "@" => {
+                let address = self.calc_term(tokens)? as usize;
+                Ok(self.memory.get(address).copied().unwrap_or(0) as f64)
+            }
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "HERE" => Ok(self.here as f64),
+            _ => {
+                if let Some(value) = Chip8OctoAssembler::parse_number(&token) {
+                    return Ok(value as f64);
+                }
+                if let Some(value) = self.values.get(&token) {
+                    return Ok(*value as f64);
+                }
+                if let Some(register) = self.register(&token) {
+                    return Ok(register as f64);
+                }
+                self.error(format!("'{}' is not defined in expression", token))
+            }
+        }
+    }
+
+    fn finish(mut self) -> io::Result<Vec<u8>> {
+        if let Some(block) = self.blocks.last() {
+            let open = match block {
+                Block::Loop(..) => "loop",
+                _ => "begin",
+            };
+            return self.error(format!("'{}' is never closed", open));
+        }
+        let Some(&main) = self.values.get("main") else {
+            return self.error("the program has no main label".to_string());
+        };
+        self.memory[Chip8OctoAssembler::PROGRAM_START_ADDRESS] = 0x10 | (main >> 8) as u8 & 0x0F;
+        self.memory[Chip8OctoAssembler::PROGRAM_START_ADDRESS + 1] = main as u8;
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let (name, high) = match fixup.name.strip_suffix("\u{0}high") {
+                Some(name) => (name, true),
+                None => (fixup.name.as_str(), false),
+            };
+            let Some(&value) = self.values.get(name) else {
+                self.line = fixup.line;
+                return self.error(format!("'{}' is never defined", name));
+            };
+            let at = fixup.address;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if !(0..=0xFFF).contains(&value) {
+                        self.line = fixup.line;
+                        return self.error(format!("'{}' does not fit in 12 bits", name));
+                    }
+                    self.memory[at] |= (value >> 8) as u8 & 0x0F;
+                    self.memory[at + 1] = value as u8;
+                }
+                FixupKind::Byte if high => self.memory[at] = (value >> 8) as u8,
+                FixupKind::Byte => self.memory[at] = value as u8,
+                FixupKind::Long => {
+                    self.memory[at] = (value >> 8) as u8;
+                    self.memory[at + 1] = value as u8;
+                }
+                FixupKind::HighNibble => self.memory[at] |= (value >> 8) as u8 & 0x0F,
+            }
+        }
+        Ok(self.memory[Chip8OctoAssembler::PROGRAM_START_ADDRESS..self.end].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn statements_assemble_to_chip8_instructions() {
+        let program = Chip8OctoAssembler::assemble(
+            ": main
+               clear
+               v0 := 5  v1 += -1  v2 := v3  v4 -= v5  v6 >>= v6
+               i := box  sprite v0 v1 8
+               v7 := random 0xFF  delay := v7  v8 := key  bcd v8  save v2  load v2
+               draw
+             : loop-forever jump loop-forever
+             : draw i := hex v0 ;
+             : box 0xFF 0x81",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&program),
+            vec![
+                0x1202, 0x00E0, 0x6005, 0x71FF, 0x8230, 0x8455, 0x8666, 0xA226, 0xD018, 0xC7FF,
+                0xF715, 0xF80A, 0xF833, 0xF255, 0xF265, 0x2222, 0x1220, 0xF029, 0x00EE, 0xFF81,
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow_patches_its_jumps() {
+        let program = Chip8OctoAssembler::assemble(
+            ": main
+               loop
+                 if v0 == 3 then v1 := 1
+                 if v0 key begin v2 := 2 else v2 := 3 end
+                 while v0 != 9
+                 v0 += 1
+               again",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&program),
+            vec![
+                0x1202, // jump main
+                0x4003, 0x6101, // if v0 == 3 then
+                0xE09E, 0x120E, 0x6202, 0x1210, 0x6203, // if key begin ... else ... end
+                0x4009, 0x1218, // while v0 != 9
+                0x7001, 0x1202, // again
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        let program = Chip8OctoAssembler::assemble(": main if v1 > 5 then v2 := 0").unwrap();
+        assert_eq!(
+            words(&program),
+            vec![0x1202, 0x6F05, 0x8F15, 0x3F01, 0x6200]
+        );
+    }
+
+    #[test]
+    fn constants_macros_and_unpack() {
+        let program = Chip8OctoAssembler::assemble(
+            ":const SPEED 3
+             :calc DOUBLE { SPEED * 2 }
+             :macro bump reg amount { reg += amount }
+             : main
+               bump v1 DOUBLE
+               :unpack 0xA data
+               :org 0x300
+             : data :byte { DOUBLE + 1 }",
+        )
+        .unwrap();
+        assert_eq!(words(&program[..8]), vec![0x1202, 0x7106, 0x60A3, 0x6100]);
+        assert_eq!(program[0x100], 7);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = Chip8OctoAssembler::assemble(": main\n  jump nowhere").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Octo source line 2: 'nowhere' is never defined"
+        );
+        let err = Chip8OctoAssembler::assemble("clear").unwrap_err();
+        assert!(err.to_string().contains("no main label"));
+    }
+}
@@ -0,0 +1,146 @@
+// Octo cartridges are GIF images with the program hidden in the pixels. The low nibble of every pixel's
+// colour index carries 4 bits of payload (high nibble first), frame after frame. The payload starts with
+// a 4 byte big endian length followed by that much UTF-8 JSON:
+//
+//   { "program": "<octo source>", "options": { "tickrate": 20, "fillColor": "#FFCC00", "shiftQuirks": true, ... } }
+//
+// The program is assembled with octo_assembler.rs.
+use super::octo_assembler::Chip8OctoAssembler;
+
+use serde::Deserialize;
+use std::io;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Chip8OctoOptions {
+    pub tickrate: Option<u32>,
+    pub fill_color: Option<String>,
+    pub background_color: Option<String>,
+    // 8xy6/8xyE shift Vx in place
+    pub shift_quirks: Option<bool>,
+    // Fx55/Fx65 leave I unchanged
+    pub load_store_quirks: Option<bool>,
+    // Bnnn jumps to nnn + Vx
+    pub jump_quirks: Option<bool>,
+    // 8xy1/8xy2/8xy3 reset VF
+    pub logic_quirks: Option<bool>,
+    // Sprites are clipped at the edge of the screen
+    pub clip_quirks: Option<bool>,
+    // Dxyn waits for vertical blank
+    pub v_blank_quirks: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct Chip8OctoPayload {
+    program: String,
+    options: Chip8OctoOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chip8Cartridge {
+    pub program: Vec<u8>,
+    pub options: Chip8OctoOptions,
+}
+
+impl Chip8OctoOptions {
+    //"#RRGGBB" colours as used by Octo
+    pub fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+        let hex = color.strip_prefix('#')?;
+        let value = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)?;
+        Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+}
+
+impl Chip8Cartridge {
+    pub fn is_cartridge(contents: &[u8]) -> bool {
+        contents.starts_with(b"GIF87a") || contents.starts_with(b"GIF89a")
+    }
+
+    pub fn from_gif(contents: &[u8]) -> io::Result<Chip8Cartridge> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(contents).map_err(io::Error::other)?;
+        let mut nibbles = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(io::Error::other)? {
+            nibbles.extend(frame.buffer.iter().map(|index| index & 0x0F));
+        }
+        let bytes = nibbles
+            .chunks_exact(2)
+            .map(|pair| (pair[0] << 4) | pair[1])
+            .collect::<Vec<u8>>();
+
+        let length = bytes
+            .get(..4)
+            .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+            .filter(|length| bytes.len() >= 4 + length)
+            .ok_or_else(|| invalid_data("GIF holds no Octo cartridge payload"))?;
+        let payload: Chip8OctoPayload = serde_json::from_slice(&bytes[4..4 + length])?;
+        Ok(Chip8Cartridge {
+            program: Chip8OctoAssembler::assemble(&payload.program)?,
+            options: payload.options,
+        })
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Hides the payload in the low nibbles of a 16 colour GIF the way Octo does
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xF])
+            .collect();
+        let width = 64;
+        pixels.resize(pixels.len().div_ceil(width) * width, 0);
+        let palette: Vec<u8> = (0..16)
+            .flat_map(|index| [index * 16, index * 16, index * 16])
+            .collect();
+        let mut res = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(
+                &mut res,
+                width as u16,
+                (pixels.len() / width) as u16,
+                &palette,
+            )
+            .unwrap();
+            let frame = gif::Frame::from_indexed_pixels(
+                width as u16,
+                (pixels.len() / width) as u16,
+                pixels,
+                None,
+            );
+            encoder.write_frame(&frame).unwrap();
+        }
+        res
+    }
+
+    #[test]
+    fn cartridge_programs_are_assembled() {
+        let json = serde_json::json!({
+            "program": ": main\n  i := ball\n  loop\n    sprite v0 v1 1\n    v0 += 1\n  again\n: ball 0x80",
+            "options": { "tickrate": 20, "shiftQuirks": true }
+        })
+        .to_string();
+        let contents = cartridge(&json);
+        assert!(Chip8Cartridge::is_cartridge(&contents));
+        let cartridge = Chip8Cartridge::from_gif(&contents).unwrap();
+        assert_eq!(
+            cartridge.program,
+            vec![0x12, 0x02, 0xA2, 0x0A, 0xD0, 0x11, 0x70, 0x01, 0x12, 0x04, 0x80]
+        );
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert_eq!(cartridge.options.shift_quirks, Some(true));
+    }
+}
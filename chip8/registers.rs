@@ -1,6 +1,6 @@
 use std::fmt::Write;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8Registers {
     // 16 general purpose 8-bit registers, usually referred to as Vx, where x is a hexadecimal digit (0 through F).
     // The F register should not be used by any program, as it is used as a flag by some instructions.
@@ -29,7 +29,7 @@ impl std::fmt::Display for Chip8Registers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         //let tmp = self.raw_array[0][0].to_string();
         let result = self.genral.iter().fold(String::new(), |mut acc_x, x| {
-            write!(&mut acc_x, "{}\n", x).unwrap();
+            writeln!(&mut acc_x, "{}", x).unwrap();
             acc_x
         });
 
@@ -0,0 +1,45 @@
+//SplitMix64, small enough that its whole state is one u64 which save states store as is
+#[derive(Debug, Clone)]
+pub struct Chip8Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Chip8Rng {
+    const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+    //Seeded from the OS so normal runs still differ, use with_seed for reproducible runs
+    pub fn new() -> Chip8Rng {
+        Chip8Rng::with_seed(rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Chip8Rng {
+        Chip8Rng { seed, state: seed }
+    }
+
+    //Rebuild a generator saved with seed and state
+    pub fn restore(seed: u64, state: u64) -> Chip8Rng {
+        Chip8Rng { seed, state }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    //Start the sequence again from the same seed
+    pub fn reset(&mut self) {
+        self.state = self.seed;
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(Chip8Rng::GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+}
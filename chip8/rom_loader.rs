@@ -0,0 +1,314 @@
+// Turns a ROM argument into the bytes loaded at 0x200. Accepted forms:
+//   -                  the ROM is read from stdin (once, later reloads reuse it)
+//   game.zip:BRIX      the entry BRIX of a zip archive, the name can be left out when there is a single file
+//   anything else      a file
+// The contents are a raw binary, whitespace separated hex text ("A2 1E C2 01", # and ; start comments,
+// "0200:" style address columns are skipped), Intel HEX with load addresses from 0x200 upwards or an
+// Octo cartridge GIF (see octo_cartridge.rs).
+use super::octo_cartridge::Chip8Cartridge;
+
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8RomFormat {
+    Binary,
+    HexText,
+    IntelHex,
+    Zip,
+    OctoCartridge,
+}
+
+static STDIN_ROM: OnceLock<Vec<u8>> = OnceLock::new();
+
+const PROGRAM_START_ADDRESS: usize = 0x200;
+const MEMORY_SIZE: usize = 4096;
+
+impl Chip8RomFormat {
+    pub fn from_name(name: &str) -> Option<Chip8RomFormat> {
+        match name {
+            "bin" => Some(Chip8RomFormat::Binary),
+            "hex" => Some(Chip8RomFormat::HexText),
+            "ihex" => Some(Chip8RomFormat::IntelHex),
+            "zip" => Some(Chip8RomFormat::Zip),
+            "octo" => Some(Chip8RomFormat::OctoCartridge),
+            _ => None,
+        }
+    }
+
+    //Text that is all hex is taken as a listing, Intel HEX records start with ':'
+    pub fn detect(contents: &[u8]) -> Chip8RomFormat {
+        if contents.starts_with(b"PK\x03\x04") {
+            return Chip8RomFormat::Zip;
+        }
+        if Chip8Cartridge::is_cartridge(contents) {
+            return Chip8RomFormat::OctoCartridge;
+        }
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) if !text.trim().is_empty() => text,
+            _ => return Chip8RomFormat::Binary,
+        };
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.all(|line| line.starts_with(':')) {
+            Chip8RomFormat::IntelHex
+        } else if parse_hex_text(text).is_ok() {
+            Chip8RomFormat::HexText
+        } else {
+            Chip8RomFormat::Binary
+        }
+    }
+}
+
+//The program and, when the ROM is a cartridge, the options that come with it, all from a single read
+#[derive(Debug, Clone)]
+pub struct Chip8Rom {
+    pub program: Vec<u8>,
+    pub cartridge: Option<Chip8Cartridge>,
+}
+
+//The format is detected from the contents unless one is given, e.g. from --format
+pub fn load(filename: &str, format: Option<Chip8RomFormat>) -> io::Result<Chip8Rom> {
+    let (format, contents) = read_contents(filename, format)?;
+    let cartridge = match format {
+        Chip8RomFormat::OctoCartridge => Some(Chip8Cartridge::from_gif(&contents)?),
+        _ => None,
+    };
+    let program = match &cartridge {
+        Some(cartridge) => cartridge.program.clone(),
+        None => decode(format, &contents)?,
+    };
+    if program.len() > MEMORY_SIZE - PROGRAM_START_ADDRESS {
+        return Err(invalid_data(format!(
+            "ROM is {} bytes, program memory only holds {}",
+            program.len(),
+            MEMORY_SIZE - PROGRAM_START_ADDRESS
+        )));
+    }
+    Ok(Chip8Rom { program, cartridge })
+}
+
+pub fn read(filename: &str, format: Option<Chip8RomFormat>) -> io::Result<Vec<u8>> {
+    load(filename, format).map(|rom| rom.program)
+}
+
+//Contents of the ROM taken out of any archive, with the format they are in
+fn read_contents(
+    filename: &str,
+    format: Option<Chip8RomFormat>,
+) -> io::Result<(Chip8RomFormat, Vec<u8>)> {
+    let (path, entry) = split_zip_entry(filename);
+    let contents = if path == "-" {
+        stdin_rom()?
+    } else {
+        fs::read(path)?
+    };
+    let is_zip = entry.is_some()
+        || match format {
+            Some(format) => format == Chip8RomFormat::Zip,
+            None => Chip8RomFormat::detect(&contents) == Chip8RomFormat::Zip,
+        };
+    let contents = if is_zip {
+        read_zip_entry(&contents, entry)?
+    } else {
+        contents
+    };
+    //An override other than zip is about the ROM itself, also when it comes out of an archive
+    let format = match format {
+        Some(Chip8RomFormat::Zip) | None => Chip8RomFormat::detect(&contents),
+        Some(format) => format,
+    };
+    Ok((format, contents))
+}
+
+fn decode(format: Chip8RomFormat, contents: &[u8]) -> io::Result<Vec<u8>> {
+    match format {
+        Chip8RomFormat::Binary => Ok(contents.to_vec()),
+        Chip8RomFormat::HexText => parse_hex_text(&text(contents)?),
+        Chip8RomFormat::IntelHex => parse_intel_hex(&text(contents)?),
+        Chip8RomFormat::Zip => Err(invalid_data("Zip archive inside a zip archive")),
+        Chip8RomFormat::OctoCartridge => Ok(Chip8Cartridge::from_gif(contents)?.program),
+    }
+}
+
+fn stdin_rom() -> io::Result<Vec<u8>> {
+    if let Some(contents) = STDIN_ROM.get() {
+        return Ok(contents.clone());
+    }
+    let mut contents = Vec::new();
+    io::stdin().read_to_end(&mut contents)?;
+    Ok(STDIN_ROM.get_or_init(|| contents).clone())
+}
+
+//"game.zip:BRIX" is the entry BRIX of game.zip
+fn split_zip_entry(filename: &str) -> (&str, Option<&str>) {
+    match filename.to_ascii_lowercase().find(".zip:") {
+        Some(index) => (&filename[..index + 4], Some(&filename[index + 5..])),
+        None => (filename, None),
+    }
+}
+
+//Entries match by full path or by file name, without a name the archive must hold a single file
+fn read_zip_entry(contents: &[u8], entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(contents)).map_err(io::Error::other)?;
+    let files = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect::<Vec<String>>();
+    let name = match entry {
+        Some(entry) => files
+            .iter()
+            .find(|name| name.as_str() == entry || name.rsplit('/').next() == Some(entry))
+            .ok_or_else(|| invalid_data(format!("No {} in the archive", entry)))?,
+        None if files.len() == 1 => &files[0],
+        None => {
+            return Err(invalid_data(format!(
+                "Archive holds several files, pick one with archive.zip:NAME ({})",
+                files.join(", ")
+            )))
+        }
+    };
+    let mut res = Vec::new();
+    archive
+        .by_name(name)
+        .map_err(io::Error::other)?
+        .read_to_end(&mut res)?;
+    Ok(res)
+}
+
+fn parse_hex_text(text: &str) -> io::Result<Vec<u8>> {
+    let mut res = Vec::new();
+    for line in text.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+        for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+            let token = token.trim_start_matches("0x").trim_start_matches('$');
+            if token.is_empty() || token.ends_with(':') {
+                continue;
+            }
+            if token.len() % 2 != 0 {
+                return Err(invalid_data(format!(
+                    "Odd number of hex digits in {}",
+                    token
+                )));
+            }
+            for index in (0..token.len()).step_by(2) {
+                let byte = token
+                    .get(index..index + 2)
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| invalid_data(format!("{} is not hex", token)))?;
+                res.push(byte);
+            }
+        }
+    }
+    if res.is_empty() {
+        return Err(invalid_data("No hex bytes found"));
+    }
+    Ok(res)
+}
+
+//Only data (00) and end of file (01) records, CHIP-8 memory never needs the extended address records
+fn parse_intel_hex(text: &str) -> io::Result<Vec<u8>> {
+    let mut res = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .filter(|record| record.len() % 2 == 0)
+            .and_then(|record| {
+                (0..record.len())
+                    .step_by(2)
+                    .map(|index| {
+                        record
+                            .get(index..index + 2)
+                            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    })
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .filter(|record| record.len() >= 5 && record.len() == record[0] as usize + 5)
+            .ok_or_else(|| invalid_data(format!("Bad Intel HEX record on line {}", number + 1)))?;
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(invalid_data(format!(
+                "Intel HEX checksum mismatch on line {}",
+                number + 1
+            )));
+        }
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => {
+                if address < PROGRAM_START_ADDRESS || address + data.len() > MEMORY_SIZE {
+                    return Err(invalid_data(format!(
+                        "Intel HEX data at {:#05X} on line {} is outside program memory",
+                        address,
+                        number + 1
+                    )));
+                }
+                let offset = address - PROGRAM_START_ADDRESS;
+                if res.len() < offset + data.len() {
+                    res.resize(offset + data.len(), 0);
+                }
+                res[offset..offset + data.len()].copy_from_slice(data);
+            }
+            0x01 => break,
+            kind => {
+                return Err(invalid_data(format!(
+                    "Unsupported Intel HEX record type {:02X} on line {}",
+                    kind,
+                    number + 1
+                )))
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn text(contents: &[u8]) -> io::Result<String> {
+    String::from_utf8(contents.to_vec()).map_err(|_| invalid_data("ROM is not text"))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex_places_data_from_the_program_start() {
+        let text = ":040200006005120281\n:01020800AB4A\n:00000001FF\n:0101000001FD\n";
+        assert_eq!(
+            parse_intel_hex(text).unwrap(),
+            vec![0x60, 0x05, 0x12, 0x02, 0, 0, 0, 0, 0xAB]
+        );
+    }
+
+    #[test]
+    fn intel_hex_rejects_bad_records() {
+        let error = |text: &str| parse_intel_hex(text).unwrap_err().to_string();
+        assert!(error(":040200006005120280\n").contains("checksum"));
+        assert!(error(":0402000060051202\n").contains("Bad Intel HEX record"));
+        assert!(error("040200006005120281\n").contains("Bad Intel HEX record"));
+        assert!(error(":0101000001FD\n").contains("outside program memory"));
+        assert!(error(":020FFF000102ED\n").contains("outside program memory"));
+        assert!(error(":020000040000FA\n").contains("record type 04"));
+    }
+
+    #[test]
+    fn intel_hex_is_detected() {
+        let text = b":040200006005120281\n:00000001FF\n";
+        assert_eq!(Chip8RomFormat::detect(text), Chip8RomFormat::IntelHex);
+        assert_eq!(
+            Chip8RomFormat::detect(b"60 05 12 02"),
+            Chip8RomFormat::HexText
+        );
+        assert_eq!(
+            Chip8RomFormat::detect(&[0x60, 0x05, 0x12, 0x02]),
+            Chip8RomFormat::Binary
+        );
+    }
+}
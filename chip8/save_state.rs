@@ -0,0 +1,277 @@
+// Save state file format, all numbers are little endian.
+//
+// Header (14 bytes)
+//   0  4  magic "C8SS"
+//   4  2  format version
+//   6  4  payload length in bytes
+//   10 4  CRC-32 (IEEE) of the payload
+//
+// Payload, version 1
+//   memory      4096 bytes
+//   registers   V0..VF (16), I (u16), special (2), PC (u16), SP (u8), stack (16 x u16)
+//   timers      delay (u8), sound (u8)
+//   display     32 rows x 8 bytes, bit n of byte b is pixel x = b * 8 + n
+//   keyboard    key map (u16), bit n set when key n is down
+//   quirks      bit flags, see Chip8Quirks::to_bits
+//   cpu speed   instructions per second (u32)
+//
+// Payload, version 2 appends
+//   rng         seed (u64), generator state (u64)
+//
+// Payload, version 3 appends
+//   timers      frames since the ROM was loaded (u64), vblank (u8, 1 when set)
+//
+// New versions only ever append fields to the payload so older files keep loading,
+// fields missing from an older payload keep their default value.
+use super::config::{Chip8Config, Chip8Quirks};
+use super::display::Chip8Display;
+use super::keyboard::Chip8Keyboard;
+use super::memory::Chip8Memory;
+use super::registers::Chip8Registers;
+use super::rng::Chip8Rng;
+use super::timers::Chip8Timers;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Cursor, Read, Write};
+
+#[derive(Debug, Clone)]
+pub struct Chip8State {
+    pub memory: Chip8Memory,
+    pub registers: Chip8Registers,
+    pub timers: Chip8Timers,
+    pub display: Chip8Display,
+    pub keyboard: Chip8Keyboard,
+    pub config: Chip8Config,
+
+    // None for version 1 states, which didn't store the generator
+    pub rng: Option<Chip8Rng>,
+}
+
+impl Chip8State {
+    pub const MAGIC: &'static [u8; 4] = b"C8SS";
+    pub const VERSION: u16 = 3;
+    const HEADER_SIZE: usize = 14;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = self.payload();
+        let mut res = Vec::with_capacity(Chip8State::HEADER_SIZE + payload.len());
+        res.extend_from_slice(Chip8State::MAGIC);
+        res.write_u16::<LittleEndian>(Chip8State::VERSION).unwrap();
+        res.write_u32::<LittleEndian>(payload.len() as u32).unwrap();
+        res.write_u32::<LittleEndian>(crc32(&payload)).unwrap();
+        res.extend_from_slice(&payload);
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Chip8State> {
+        if bytes.len() < Chip8State::HEADER_SIZE || &bytes[0..4] != Chip8State::MAGIC {
+            return Err(invalid_data("Not a chip8 save state"));
+        }
+        let mut header = Cursor::new(&bytes[4..Chip8State::HEADER_SIZE]);
+        let version = header.read_u16::<LittleEndian>()?;
+        let length = header.read_u32::<LittleEndian>()? as usize;
+        let checksum = header.read_u32::<LittleEndian>()?;
+
+        if version == 0 || version > Chip8State::VERSION {
+            return Err(invalid_data(&format!(
+                "Unsupported save state version {}",
+                version
+            )));
+        }
+        let payload = bytes
+            .get(Chip8State::HEADER_SIZE..Chip8State::HEADER_SIZE + length)
+            .ok_or_else(|| invalid_data("Save state is truncated"))?;
+        if crc32(payload) != checksum {
+            return Err(invalid_data("Save state checksum does not match"));
+        }
+
+        let mut state = Chip8State {
+            memory: Chip8Memory::new(),
+            registers: Chip8Registers::new(),
+            timers: Chip8Timers::new(),
+            display: Chip8Display::new(),
+            keyboard: Chip8Keyboard::new(),
+            config: Chip8Config::new(),
+            rng: None,
+        };
+        let mut reader = Cursor::new(payload);
+
+        //Version 1
+        reader.read_exact(&mut state.memory.raw_array)?;
+        reader.read_exact(&mut state.registers.genral)?;
+        state.registers.memory_address = reader.read_u16::<LittleEndian>()?;
+        reader.read_exact(&mut state.registers.special)?;
+        state.registers.program_counter = reader.read_u16::<LittleEndian>()?;
+        state.registers.stack_pointer = reader.read_u8()?;
+        reader.read_u16_into::<LittleEndian>(&mut state.registers.stack)?;
+        state.timers.delay_timer = reader.read_u8()?;
+        state.timers.sound_timer = reader.read_u8()?;
+        let mut rows = [[0; Chip8Display::WIDTH / 8]; Chip8Display::HEIGHT];
+        for row in rows.iter_mut() {
+            reader.read_exact(row)?;
+        }
+        state.display.set_raw_rows(rows);
+        state
+            .keyboard
+            .set_key_map(reader.read_u16::<LittleEndian>()?);
+        state.config.quirks = Chip8Quirks::from_bits(reader.read_u8()?);
+        state.config.cpu_hz = reader.read_u32::<LittleEndian>()?;
+
+        //Version 2
+        if version >= 2 {
+            let seed = reader.read_u64::<LittleEndian>()?;
+            let rng_state = reader.read_u64::<LittleEndian>()?;
+            state.rng = Some(Chip8Rng::restore(seed, rng_state));
+        }
+
+        //Version 3
+        if version >= 3 {
+            state.timers.frame = reader.read_u64::<LittleEndian>()?;
+            state.timers.vblank = reader.read_u8()? != 0;
+        }
+
+        Ok(state)
+    }
+
+    //FNV-1a over the payload, used to check that a replay ends in the same state
+    pub fn hash(&self) -> u64 {
+        self.payload()
+            .iter()
+            .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+            })
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::new();
+        res.write_all(&self.memory.raw_array).unwrap();
+        res.write_all(&self.registers.genral).unwrap();
+        res.write_u16::<LittleEndian>(self.registers.memory_address)
+            .unwrap();
+        res.write_all(&self.registers.special).unwrap();
+        res.write_u16::<LittleEndian>(self.registers.program_counter)
+            .unwrap();
+        res.write_u8(self.registers.stack_pointer).unwrap();
+        for data in self.registers.stack.iter() {
+            res.write_u16::<LittleEndian>(*data).unwrap();
+        }
+        res.write_u8(self.timers.delay_timer).unwrap();
+        res.write_u8(self.timers.sound_timer).unwrap();
+        for row in self.display.get_raw_rows().iter() {
+            res.write_all(row).unwrap();
+        }
+        res.write_u16::<LittleEndian>(self.keyboard.get_key_map())
+            .unwrap();
+        res.write_u8(self.config.quirks.to_bits()).unwrap();
+        res.write_u32::<LittleEndian>(self.config.cpu_hz).unwrap();
+        //Always written so the version 3 fields stay at their offsets, a state without a generator stores zeros
+        let (seed, rng_state) = self
+            .rng
+            .as_ref()
+            .map(|rng| (rng.seed(), rng.state()))
+            .unwrap_or_default();
+        res.write_u64::<LittleEndian>(seed).unwrap();
+        res.write_u64::<LittleEndian>(rng_state).unwrap();
+        res.write_u64::<LittleEndian>(self.timers.frame).unwrap();
+        res.write_u8(self.timers.vblank as u8).unwrap();
+        res
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//Bitwise CRC-32 (IEEE 802.3), save states are small enough that a lookup table isn't needed
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Chip8State {
+        let mut state = Chip8State {
+            memory: Chip8Memory::new(),
+            registers: Chip8Registers::new(),
+            timers: Chip8Timers::new(),
+            display: Chip8Display::new(),
+            keyboard: Chip8Keyboard::new(),
+            config: Chip8Config::new(),
+            rng: Some(Chip8Rng::with_seed(42)),
+        };
+        state.memory.raw_array[0x200] = 0xA2;
+        state.memory.raw_array[0xFFF] = 0x5A;
+        state.registers.genral[0xF] = 7;
+        state.registers.memory_address = 0x345;
+        state.registers.program_counter = 0x2F0;
+        state.registers.stack_pointer = 2;
+        state.registers.stack[1] = 0x208;
+        state.timers.delay_timer = 30;
+        state.timers.sound_timer = 4;
+        state.timers.frame = 1234;
+        state.timers.vblank = true;
+        state.display.set_pixel(3, 5);
+        state.display.set_pixel(63, 31);
+        state.keyboard.set_key_map(0x8001);
+        state.config.cpu_hz = 1000;
+        state.rng.as_mut().unwrap().next_byte();
+        state
+    }
+
+    #[test]
+    fn round_trip_keeps_the_whole_state() {
+        let mut saved = state();
+        let mut loaded = Chip8State::from_bytes(&saved.to_bytes()).unwrap();
+
+        assert_eq!(loaded.hash(), saved.hash());
+        assert_eq!(loaded.memory.raw_array[0xFFF], 0x5A);
+        assert_eq!(loaded.registers.program_counter, 0x2F0);
+        assert_eq!(loaded.registers.stack[1], 0x208);
+        assert_eq!(loaded.timers.frame, 1234);
+        assert!(loaded.timers.vblank);
+        assert!(loaded.display.get_pixel(63, 31));
+        assert!(!loaded.display.get_pixel(4, 5));
+        assert_eq!(loaded.keyboard.get_key_map(), 0x8001);
+        assert_eq!(loaded.config.cpu_hz, 1000);
+        let (saved_rng, loaded_rng) = (saved.rng.as_mut().unwrap(), loaded.rng.as_mut().unwrap());
+        assert_eq!(loaded_rng.seed(), 42);
+        assert_eq!(loaded_rng.next_byte(), saved_rng.next_byte());
+    }
+
+    #[test]
+    fn round_trip_without_a_generator_keeps_the_later_fields() {
+        let mut saved = state();
+        saved.rng = None;
+        let loaded = Chip8State::from_bytes(&saved.to_bytes()).unwrap();
+
+        assert_eq!(loaded.hash(), saved.hash());
+        assert_eq!(loaded.timers.frame, 1234);
+        assert!(loaded.timers.vblank);
+        let loaded_rng = loaded.rng.unwrap();
+        assert_eq!((loaded_rng.seed(), loaded_rng.state()), (0, 0));
+    }
+
+    #[test]
+    fn corrupted_payload_is_rejected() {
+        let mut bytes = state().to_bytes();
+        bytes[Chip8State::HEADER_SIZE + 0x200] ^= 0xFF;
+        assert!(Chip8State::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_and_foreign_files_are_rejected() {
+        let bytes = state().to_bytes();
+        assert!(Chip8State::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Chip8State::from_bytes(b"GIF89a").is_err());
+    }
+}
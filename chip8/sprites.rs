@@ -0,0 +1,141 @@
+use super::display::Chip8Display;
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::{fs, io, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chip8Sprite {
+    pub address: u16,
+    pub height: u8,
+}
+
+#[derive(Debug)]
+pub struct Chip8SpriteTracker {
+    sprites: BTreeSet<Chip8Sprite>,
+}
+
+impl Chip8Sprite {
+    //Sprites are 8 pixels wide, one byte per row with the most significant bit on the left
+    pub fn rows<'a>(self, memory: &'a [u8]) -> impl Iterator<Item = [bool; 8]> + 'a {
+        let start = (self.address as usize).min(memory.len());
+        let end = (self.address as usize + self.height as usize).min(memory.len());
+        memory[start..end].iter().map(|byte| {
+            let mut row = [false; 8];
+            for (bit_index, pixel) in row.iter_mut().enumerate() {
+                *pixel = (byte & (128 >> bit_index)) > 0;
+            }
+            row
+        })
+    }
+
+    pub fn to_pbm(self, memory: &[u8]) -> String {
+        let mut res = String::new();
+        writeln!(&mut res, "P1").unwrap();
+        writeln!(
+            &mut res,
+            "# sprite at {:#05X}, height {}",
+            self.address, self.height
+        )
+        .unwrap();
+        writeln!(&mut res, "8 {}", self.height).unwrap();
+        for row in self.rows(memory) {
+            let line = row
+                .iter()
+                .map(|bit| if *bit { "1" } else { "0" })
+                .collect::<Vec<&str>>()
+                .join(" ");
+            writeln!(&mut res, "{}", line).unwrap();
+        }
+        res
+    }
+
+    pub fn to_ascii(self, memory: &[u8]) -> String {
+        let mut res = String::new();
+        writeln!(&mut res, "{:#05X} (height {})", self.address, self.height).unwrap();
+        for row in self.rows(memory) {
+            writeln!(&mut res, "{}", Chip8Display::render_row(row)).unwrap();
+        }
+        res
+    }
+}
+
+impl Chip8SpriteTracker {
+    pub fn new() -> Chip8SpriteTracker {
+        Chip8SpriteTracker {
+            sprites: BTreeSet::new(),
+        }
+    }
+
+    //Called from Dxyn with the current value of I
+    pub fn record(&mut self, address: u16, height: u8) {
+        if height > 0 {
+            self.sprites.insert(Chip8Sprite { address, height });
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn sprites(&self) -> impl Iterator<Item = &Chip8Sprite> {
+        self.sprites.iter()
+    }
+
+    //Linear sweep over the program looking for Annn .. Dxyn pairs.
+    //Fx1E makes I relative to an unknown register so only the table base is recorded,
+    //the remaining entries of a sprite table are picked up by recording during emulation.
+    pub fn find_static(&mut self, program: &[u8], start_address: u16) {
+        let mut current_i: Option<u16> = None;
+        for data in program.chunks_exact(2) {
+            let instruction = u16::from_be_bytes([data[0], data[1]]);
+            match instruction >> 12 {
+                0xA => {
+                    current_i = Some(instruction & 0x0FFF);
+                }
+                0xD => {
+                    if let Some(address) = current_i {
+                        self.record(address, (instruction & 0x000F) as u8);
+                    }
+                }
+                0xF => {
+                    // Fx1E keeps I inside the same table, Fx29 points it at the built in font
+                    if instruction as u8 == 0x29 {
+                        current_i = None;
+                    }
+                }
+                0x1 | 0xB => {
+                    // Unconditional jump, the next instruction starts a new block
+                    current_i = None;
+                }
+                _ => {
+                    if instruction == 0x00EE {
+                        current_i = None;
+                    }
+                }
+            }
+            // Data mixed in with code can decode as anything, ignore addresses outside the program
+            if let Some(address) = current_i {
+                let end = start_address as usize + program.len();
+                if (address as usize) < start_address as usize || address as usize >= end {
+                    current_i = None;
+                }
+            }
+        }
+    }
+
+    //Write every sprite as a PBM image and as text, returns the number of sprites written
+    pub fn export(&self, memory: &[u8], dir: &Path) -> io::Result<usize> {
+        fs::create_dir_all(dir)?;
+        let mut all_ascii = String::new();
+        for sprite in self.sprites.iter() {
+            let name = format!("sprite_{:03X}_{}", sprite.address, sprite.height);
+            fs::write(dir.join(format!("{}.pbm", name)), sprite.to_pbm(memory))?;
+            let ascii = sprite.to_ascii(memory);
+            fs::write(dir.join(format!("{}.txt", name)), &ascii)?;
+            writeln!(&mut all_ascii, "{}", ascii).unwrap();
+        }
+        fs::write(dir.join("sprites.txt"), all_ascii)?;
+        Ok(self.sprites.len())
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//Amounts added over the last WINDOW, kept in buckets so a busy counter stays small
+#[derive(Debug, Clone)]
+pub struct Chip8RateCounter {
+    created: Instant,
+    buckets: VecDeque<(Instant, u64)>,
+}
+
+impl Chip8RateCounter {
+    pub const WINDOW: Duration = Duration::from_secs(2);
+    const BUCKET: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Chip8RateCounter {
+        Chip8RateCounter {
+            created: Instant::now(),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    pub fn add(&mut self, amount: u64) {
+        let now = Instant::now();
+        match self.buckets.back_mut() {
+            Some((start, total)) if now.duration_since(*start) < Chip8RateCounter::BUCKET => {
+                *total += amount
+            }
+            _ => self.buckets.push_back((now, amount)),
+        }
+        while let Some((start, _)) = self.buckets.front() {
+            if now.duration_since(*start) <= Chip8RateCounter::WINDOW + Chip8RateCounter::BUCKET {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    pub fn add_duration(&mut self, duration: Duration) {
+        self.add(duration.as_nanos() as u64);
+    }
+
+    //Amount a second over the window, or over the time since the counter was created when that is shorter.
+    //Drops to zero when nothing is added any more
+    pub fn rate(&self) -> f64 {
+        let now = Instant::now();
+        let window = now
+            .duration_since(self.created)
+            .min(Chip8RateCounter::WINDOW);
+        let total: u64 = self
+            .buckets
+            .iter()
+            .filter(|(start, _)| now.duration_since(*start) < window)
+            .map(|(_, total)| total)
+            .sum();
+        total as f64 / window.as_secs_f64().max(0.001)
+    }
+
+    //Share of the time spent in what add_duration was given, in percent
+    pub fn busy_percent(&self) -> f64 {
+        self.rate() / 10_000_000.0
+    }
+}
+
+//Measured by the clock while the machine runs, shown in the debug panel to check it keeps up
+#[derive(Debug, Clone)]
+pub struct Chip8Stats {
+    pub instructions: Chip8RateCounter,
+    pub timer_ticks: Chip8RateCounter,
+
+    // Time the clock spends running instructions and ticking the timers
+    pub cpu_busy: Chip8RateCounter,
+}
+
+impl Chip8Stats {
+    pub fn new() -> Chip8Stats {
+        Chip8Stats {
+            instructions: Chip8RateCounter::new(),
+            timer_ticks: Chip8RateCounter::new(),
+            cpu_busy: Chip8RateCounter::new(),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, io, path::Path};
+
+#[derive(Debug)]
+pub struct Chip8Symbols {
+    by_address: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+}
+
+impl Chip8Symbols {
+    pub fn new() -> Chip8Symbols {
+        Chip8Symbols {
+            by_address: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    //Each line holds one address and one name in either order, e.g. "0x2A4 draw_ball",
+    //"draw_ball = 0x2A4" or an Octo listing line "0x02A4 : draw_ball". Lines starting with # are comments.
+    pub fn load_file(&mut self, filename: &Path) -> io::Result<usize> {
+        let contents = fs::read_to_string(filename)?;
+        let mut count = 0;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (address, name) = match line.split_once('=') {
+                Some((name, address)) => (
+                    Chip8Symbols::parse_address(address.trim()),
+                    Some(name.trim()),
+                ),
+                None => {
+                    let tokens = line
+                        .split(|c: char| c.is_whitespace() || c == ':' || c == ',')
+                        .filter(|t| !t.is_empty())
+                        .collect::<Vec<&str>>();
+                    // A 0x prefixed token is always the address, otherwise the address comes first
+                    let index = tokens
+                        .iter()
+                        .position(|t| t.starts_with("0x") || t.starts_with("0X"))
+                        .unwrap_or(0);
+                    (
+                        tokens
+                            .get(index)
+                            .and_then(|t| Chip8Symbols::parse_address(t)),
+                        tokens
+                            .iter()
+                            .enumerate()
+                            .find(|(i, t)| *i != index && Chip8Symbols::is_name(t))
+                            .map(|(_, t)| *t),
+                    )
+                }
+            };
+            if let (Some(address), Some(name)) =
+                (address, name.filter(|n| Chip8Symbols::is_name(n)))
+            {
+                self.insert(address, name);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.by_address.insert(address, name.to_string());
+        self.by_name.insert(name.to_string(), address);
+    }
+
+    pub fn clear(&mut self) {
+        self.by_address.clear();
+        self.by_name.clear();
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(|name| name.as_str())
+    }
+
+    //Address as hex followed by its label when one is known, e.g. "0x2A4 <draw_ball>"
+    pub fn format_address(&self, address: u16) -> String {
+        match self.label(address) {
+            Some(name) => format!("{:#05X} <{}>", address, name),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    //Accepts a label name or a hex address (with or without 0x)
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        self.by_name
+            .get(text)
+            .copied()
+            .or_else(|| Chip8Symbols::parse_address(text))
+    }
+
+    fn parse_address(text: &str) -> Option<u16> {
+        let digits = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .unwrap_or(text);
+        if digits.is_empty() || digits.len() > 4 {
+            return None;
+        }
+        u16::from_str_radix(digits, 16)
+            .ok()
+            .filter(|address| *address <= 0x0FFF)
+    }
+
+    fn is_name(text: &str) -> bool {
+        text.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && text
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    }
+}
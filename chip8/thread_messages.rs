@@ -3,4 +3,6 @@ pub enum Chip8ControlMessage {
     Stop,
     Start,
     Step,
+    // One frame of the master clock, instructions and a timer tick
+    Frame,
 }
@@ -1,7 +1,13 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8Timers {
     pub delay_timer: u8,
     pub sound_timer: u8,
+
+    // Number of 60Hz timer ticks since the ROM was loaded
+    pub frame: u64,
+
+    // Set by every tick and cleared by a sprite drawn with the display wait quirk
+    pub vblank: bool,
 }
 
 impl Chip8Timers {
@@ -9,6 +15,20 @@ impl Chip8Timers {
         Chip8Timers {
             delay_timer: 0,
             sound_timer: 0,
+            frame: 0,
+            vblank: false,
+        }
+    }
+
+    //Decrement both timers, called once per frame
+    pub fn tick(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
         }
+        self.frame += 1;
+        self.vblank = true;
     }
 }
@@ -0,0 +1,166 @@
+// Cost of CHIP-8 instructions on the COSMAC VIP in CDP1802 machine cycles (8 clocks of the 1.7609MHz crystal),
+// counted from the routines of the VIP interpreter as disassembled by Laurence Scotford ("Chip-8 on the COSMAC
+// VIP"). Every 1802 instruction takes 2 machine cycles, the long branches 3.
+//
+// Every instruction pays the 40 cycles of the fetch and decode loop, then its own routine:
+//
+//   00E0         24, then 6 for each of the 256 bytes of the display buffer
+//   00EE         10
+//   0nnn         10 plus the machine code routine, which isn't counted
+//   1nnn / Annn  12
+//   2nnn         26
+//   3xkk / 4xkk  10, +4 when the skip is taken (two more INC of the PC register)
+//   5xy0 / 9xy0  18, +4 when the skip is taken
+//   6xkk         6
+//   7xkk         10
+//   8xyn         44, all of them run through the same self modifying code
+//   Bnnn         22
+//   Cxkk         36
+//   Dxyn         26, then per row 34 plus 8 for every bit the row is shifted right (Vx mod 8)
+//   Ex9E / ExA1  14, +4 when the skip is taken
+//   Fx07 / Fx15 / Fx18   10
+//   Fx0A         10 every time it polls the keypad
+//   Fx1E         16, +6 when I crosses into the next page
+//   Fx29         18
+//   Fx33         40, then 16 for each subtraction of the digit loops (the sum of the three digits)
+//   Fx55 / Fx65  18, then 14 for each register
+//
+// Of every 60Hz frame the 1861 display chip takes 128 lines of 8 DMA cycles and its interrupt routine,
+// which also counts the timers down, 100 more. The interpreter gets the rest.
+use super::keyboard::Chip8Keyboard;
+use super::registers::Chip8Registers;
+
+pub struct Chip8VipTiming;
+
+impl Chip8VipTiming {
+    // 1_760_900 clocks a second / 8 clocks per machine cycle / 60 frames
+    pub const CYCLES_PER_FRAME: u32 = 3668;
+    const DISPLAY_DMA_CYCLES: u32 = 128 * 8;
+    const INTERRUPT_CYCLES: u32 = 100;
+
+    // Left for the interpreter every frame
+    pub const BUDGET: u32 = Chip8VipTiming::CYCLES_PER_FRAME
+        - Chip8VipTiming::DISPLAY_DMA_CYCLES
+        - Chip8VipTiming::INTERRUPT_CYCLES;
+
+    const FETCH_CYCLES: u32 = 40;
+    const SKIP_TAKEN_CYCLES: u32 = 4;
+
+    const CLEAR_CYCLES: u32 = 24;
+    const CLEAR_BYTE_CYCLES: u32 = 6;
+
+    const SPRITE_CYCLES: u32 = 26;
+    const SPRITE_ROW_CYCLES: u32 = 34;
+    const SPRITE_SHIFT_CYCLES: u32 = 8;
+
+    const ADD_I_PAGE_CYCLES: u32 = 6;
+    const BCD_CYCLES: u32 = 40;
+    const BCD_DIGIT_CYCLES: u32 = 16;
+    const LOAD_STORE_CYCLES: u32 = 18;
+    const LOAD_STORE_REGISTER_CYCLES: u32 = 14;
+
+    //Machine cycles instruction takes with the registers and keypad as they are before it runs
+    pub fn cost(instruction: u16, registers: &Chip8Registers, keyboard: &Chip8Keyboard) -> u32 {
+        let x = ((instruction >> 8) & 0x000F) as usize;
+        let y = ((instruction >> 4) & 0x000F) as usize;
+        let kk = instruction as u8;
+        let vx = registers.genral[x];
+        let skipped = match instruction & 0xF00F {
+            0x3000..=0x300F => vx == kk,
+            0x4000..=0x400F => vx != kk,
+            0x5000 => vx == registers.genral[y],
+            0x9000 => vx != registers.genral[y],
+            0xE00E if kk == 0x9E => keyboard.get_key(vx),
+            0xE001 if kk == 0xA1 => !keyboard.get_key(vx),
+            _ => false,
+        };
+        let skip = if skipped {
+            Chip8VipTiming::SKIP_TAKEN_CYCLES
+        } else {
+            0
+        };
+        let execute = match instruction >> 12 {
+            0x0 => match instruction {
+                0x00E0 => Chip8VipTiming::CLEAR_CYCLES + 256 * Chip8VipTiming::CLEAR_BYTE_CYCLES,
+                _ => 10,
+            },
+            0x1 | 0xA => 12,
+            0x2 => 26,
+            0x3 | 0x4 => 10,
+            0x5 | 0x9 => 18,
+            0x6 => 6,
+            0x7 => 10,
+            0x8 => 44,
+            0xB => 22,
+            0xC => 36,
+            0xD => {
+                let rows = (instruction & 0x000F) as u32;
+                let shift = vx as u32 % 8;
+                Chip8VipTiming::SPRITE_CYCLES
+                    + rows
+                        * (Chip8VipTiming::SPRITE_ROW_CYCLES
+                            + shift * Chip8VipTiming::SPRITE_SHIFT_CYCLES)
+            }
+            0xE => 14,
+            _ => match kk {
+                0x1E => {
+                    let i = registers.memory_address;
+                    if (i & 0xFF) + vx as u16 > 0xFF {
+                        16 + Chip8VipTiming::ADD_I_PAGE_CYCLES
+                    } else {
+                        16
+                    }
+                }
+                0x29 => 18,
+                0x33 => {
+                    let value = vx as u32;
+                    Chip8VipTiming::BCD_CYCLES
+                        + Chip8VipTiming::BCD_DIGIT_CYCLES
+                            * (value / 100 + value / 10 % 10 + value % 10)
+                }
+                0x55 | 0x65 => {
+                    Chip8VipTiming::LOAD_STORE_CYCLES
+                        + Chip8VipTiming::LOAD_STORE_REGISTER_CYCLES * (x as u32 + 1)
+                }
+                // Fx07, Fx0A (each time it polls), Fx15, Fx18
+                _ => 10,
+            },
+        };
+        Chip8VipTiming::FETCH_CYCLES + execute + skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(instruction: u16, registers: &Chip8Registers) -> u32 {
+        Chip8VipTiming::cost(instruction, registers, &Chip8Keyboard::new())
+    }
+
+    #[test]
+    fn costs_depend_on_the_data() {
+        let mut registers = Chip8Registers::new();
+        registers.genral[1] = 3;
+        registers.genral[2] = 8;
+        registers.genral[3] = 199;
+        assert_eq!(cost(0x00E0, &registers), 40 + 24 + 256 * 6);
+        // Skips
+        assert_eq!(cost(0x3103, &registers), 40 + 10 + 4);
+        assert_eq!(cost(0x3104, &registers), 40 + 10);
+        assert_eq!(cost(0x9120, &registers), 40 + 18 + 4);
+        assert_eq!(cost(0xE1A1, &registers), 40 + 14 + 4);
+        assert_eq!(cost(0xE19E, &registers), 40 + 14);
+        // Sprites, aligned and shifted by 3
+        assert_eq!(cost(0xD205, &registers), 40 + 26 + 5 * 34);
+        assert_eq!(cost(0xD125, &registers), 40 + 26 + 5 * (34 + 3 * 8));
+        // BCD of 199 runs 1 + 9 + 9 subtractions
+        assert_eq!(cost(0xF333, &registers), 40 + 40 + 19 * 16);
+        assert_eq!(cost(0xF055, &registers), 40 + 18 + 14);
+        assert_eq!(cost(0xF565, &registers), 40 + 18 + 6 * 14);
+        // I crossing a page
+        registers.memory_address = 0x2FE;
+        assert_eq!(cost(0xF11E, &registers), 40 + 16 + 6);
+        assert_eq!(cost(0xF01E, &registers), 40 + 16);
+    }
+}
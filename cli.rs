@@ -0,0 +1,192 @@
+use std::env;
+
+pub struct CliArgs {
+    pub rom: String,
+    pub dump_sprites: Option<String>,
+    pub symbols: Option<String>,
+    pub breakpoints: Vec<String>,
+    pub disassemble: bool,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub headless: bool,
+    pub seed: Option<u64>,
+    pub scale: Option<usize>,
+    pub gif: Option<String>,
+    pub format: Option<String>,
+    pub renderer: Option<String>,
+    pub palette: Option<String>,
+    pub phosphor: Option<String>,
+    pub decay: Option<u8>,
+    pub timing: Option<String>,
+    pub speed: Option<String>,
+    pub frames: Option<u64>,
+    pub cycles: Option<u64>,
+    pub until: Vec<String>,
+    pub keys: Option<String>,
+    pub screen: Option<String>,
+    pub report: Option<String>,
+    pub golden: Option<String>,
+    pub bless: bool,
+}
+
+impl CliArgs {
+    const DEFAULT_ROM: &'static str = "roms/BRIX";
+
+    pub fn parse() -> Result<CliArgs, String> {
+        let mut args = CliArgs {
+            rom: CliArgs::DEFAULT_ROM.to_string(),
+            dump_sprites: None,
+            symbols: None,
+            breakpoints: Vec::new(),
+            disassemble: false,
+            record: None,
+            play: None,
+            headless: false,
+            seed: None,
+            scale: None,
+            gif: None,
+            format: None,
+            renderer: None,
+            palette: None,
+            phosphor: None,
+            decay: None,
+            timing: None,
+            speed: None,
+            frames: None,
+            cycles: None,
+            until: Vec::new(),
+            keys: None,
+            screen: None,
+            report: None,
+            golden: None,
+            bless: false,
+        };
+        let mut iter = env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--dump-sprites" => {
+                    args.dump_sprites = Some(
+                        iter.next()
+                            .ok_or("--dump-sprites expects an output directory")?,
+                    );
+                }
+                "--symbols" => {
+                    args.symbols = Some(iter.next().ok_or("--symbols expects a symbol file")?);
+                }
+                "--break" => {
+                    args.breakpoints
+                        .push(iter.next().ok_or("--break expects an address or label")?);
+                }
+                "--disassemble" => {
+                    args.disassemble = true;
+                }
+                "--record" => {
+                    args.record = Some(iter.next().ok_or("--record expects a movie file")?);
+                }
+                "--play" => {
+                    args.play = Some(iter.next().ok_or("--play expects a movie file")?);
+                }
+                "--headless" => {
+                    args.headless = true;
+                }
+                "--seed" => {
+                    let seed = iter.next().ok_or("--seed expects a number")?;
+                    let parsed = match seed.strip_prefix("0x") {
+                        Some(hex) => u64::from_str_radix(hex, 16),
+                        None => seed.parse::<u64>(),
+                    };
+                    args.seed = Some(parsed.map_err(|_| format!("Invalid seed {}", seed))?);
+                }
+                "--scale" => {
+                    let scale = iter.next().ok_or("--scale expects a number")?;
+                    args.scale = Some(
+                        scale
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|scale| *scale > 0)
+                            .ok_or(format!("Invalid scale {}", scale))?,
+                    );
+                }
+                "--gif" => {
+                    args.gif = Some(iter.next().ok_or("--gif expects an output file")?);
+                }
+                "--format" => {
+                    args.format = Some(
+                        iter.next()
+                            .ok_or("--format expects bin, hex, ihex, zip or octo")?,
+                    );
+                }
+                "--renderer" => {
+                    args.renderer = Some(iter.next().ok_or(
+                        "--renderer expects braille, half, full, sixel, kitty or graphics",
+                    )?);
+                }
+                "--timing" => {
+                    args.timing = Some(iter.next().ok_or("--timing expects instructions or vip")?);
+                }
+                "--speed" => {
+                    args.speed = Some(iter.next().ok_or("--speed expects a multiplier or turbo")?);
+                }
+                "--frames" => {
+                    let frames = iter.next().ok_or("--frames expects a number")?;
+                    args.frames = Some(
+                        frames
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid frames {}", frames))?,
+                    );
+                }
+                "--cycles" => {
+                    let cycles = iter.next().ok_or("--cycles expects a number")?;
+                    args.cycles = Some(
+                        cycles
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid cycles {}", cycles))?,
+                    );
+                }
+                "--until" => {
+                    args.until
+                        .push(iter.next().ok_or("--until expects loop, exit or key")?);
+                }
+                "--keys" => {
+                    args.keys = Some(iter.next().ok_or("--keys expects a key script")?);
+                }
+                "--screen" => {
+                    args.screen = Some(iter.next().ok_or("--screen expects an output file")?);
+                }
+                "--report" => {
+                    args.report = Some(iter.next().ok_or("--report expects an output file")?);
+                }
+                "--golden" => {
+                    args.golden = Some(iter.next().ok_or("--golden expects a directory")?);
+                }
+                "--bless" => {
+                    args.bless = true;
+                }
+                "--palette" => {
+                    args.palette = Some(iter.next().ok_or("--palette expects a palette name")?);
+                }
+                "--phosphor" => {
+                    args.phosphor =
+                        Some(iter.next().ok_or("--phosphor expects off, fade or blend")?);
+                }
+                "--decay" => {
+                    let decay = iter.next().ok_or("--decay expects a number of frames")?;
+                    args.decay = Some(
+                        decay
+                            .parse::<u8>()
+                            .ok()
+                            .filter(|decay| *decay > 0)
+                            .ok_or(format!("Invalid decay {}", decay))?,
+                    );
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(format!("Unknown option {}", arg));
+                }
+                _ => {
+                    args.rom = arg;
+                }
+            }
+        }
+        Ok(args)
+    }
+}
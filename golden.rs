@@ -0,0 +1,300 @@
+// Golden screen regression runs (--golden DIR): every ROM in DIR runs headlessly and its final screen is
+// compared with the golden screen stored next to it, <name>.txt as written by screenshot::to_ascii or
+// <name>.pbm, both unscaled. --bless writes the goldens from the current results instead.
+//
+// <name>.keys is used as the key script when it exists. golden.toml in DIR is optional, every key too:
+//
+//   [defaults]
+//   frames = 600            frames each ROM runs
+//   seed = 0                seed for the random number generator, runs must be repeatable
+//
+//   [roms.<name>]           file name of the ROM without the extension
+//   frames = 1200
+//   keys = "intro.keys"     key script, relative to DIR
+//   until = ["loop"]        halt conditions as for --until
+use crate::chip8::batch::{Chip8Batch, Chip8Halt};
+use crate::chip8::display::Chip8Display;
+use crate::chip8::Chip8;
+use crate::rom_browser::RomBrowser;
+use crate::screenshot;
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoldenSettings {
+    pub frames: Option<u64>,
+    pub seed: Option<u64>,
+    pub keys: Option<String>,
+    pub until: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoldenManifest {
+    pub defaults: GoldenSettings,
+    pub roms: HashMap<String, GoldenSettings>,
+}
+
+type Screen = Vec<Vec<bool>>;
+
+pub const MANIFEST_FILE: &str = "golden.toml";
+const DEFAULT_FRAMES: u64 = 600;
+
+//ROM files in dir in name order
+pub fn roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut roms = fs::read_dir(dir)
+        .map_err(|err| format!("Cannot read {}: {}", dir.display(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| RomBrowser::is_rom(path))
+        .collect::<Vec<PathBuf>>();
+    roms.sort();
+    Ok(roms)
+}
+
+//Runs every ROM in dir, prints a line for each and the pixel diff of failures. Returns how many failed
+pub fn run(dir: &Path, bless: bool) -> Result<usize, String> {
+    let manifest = match fs::read_to_string(dir.join(MANIFEST_FILE)) {
+        Ok(contents) => toml::from_str::<GoldenManifest>(&contents)
+            .map_err(|err| format!("{}: {}", MANIFEST_FILE, err))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => GoldenManifest::default(),
+        Err(err) => return Err(format!("{}: {}", MANIFEST_FILE, err)),
+    };
+    let roms = roms(dir)?;
+
+    let mut failed = 0;
+    for rom in roms.iter() {
+        let name = rom
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let settings = manifest.roms.get(&name);
+        match run_rom(rom, &manifest.defaults, settings, bless) {
+            Ok(message) => println!("ok    {}{}", name, message),
+            Err(message) => {
+                failed += 1;
+                println!("FAIL  {}: {}", name, message);
+            }
+        }
+    }
+    println!("{} of {} ROMs passed", roms.len() - failed, roms.len());
+    Ok(failed)
+}
+
+//The machine is built from the ROM alone with the default config, chip8.toml, the ROM database and the
+//recent ROMs are never read or written so results only depend on what is in dir
+fn run_rom(
+    rom: &Path,
+    defaults: &GoldenSettings,
+    settings: Option<&GoldenSettings>,
+    bless: bool,
+) -> Result<String, String> {
+    let setting =
+        |get: fn(&GoldenSettings) -> Option<u64>| settings.and_then(get).or_else(|| get(defaults));
+    let filename = rom.to_string_lossy().to_string();
+    let sys = Chip8::new(&filename, None).map_err(|err| format!("cannot load: {}", err))?;
+    sys.set_seed(setting(|settings| settings.seed).unwrap_or(0));
+
+    let mut batch = Chip8Batch::new();
+    batch.frames = setting(|settings| settings.frames).unwrap_or(DEFAULT_FRAMES);
+    let until = settings.map_or(&defaults.until, |settings| &settings.until);
+    for name in until.iter() {
+        batch
+            .halts
+            .push(Chip8Halt::from_name(name).ok_or(format!("unknown halt condition {}", name))?);
+    }
+    let keys = match settings.and_then(|settings| settings.keys.as_ref()) {
+        Some(keys) => Some(rom.with_file_name(keys)),
+        None => Some(rom.with_extension("keys")).filter(|keys| keys.is_file()),
+    };
+    if let Some(keys) = keys {
+        let contents = fs::read_to_string(&keys)
+            .map_err(|err| format!("cannot read {}: {}", keys.display(), err))?;
+        batch.keys = Chip8Batch::parse_keys(&contents)
+            .map_err(|err| format!("{}: {}", keys.display(), err))?;
+    }
+
+    let result = sys.run_batch(&batch);
+    if result.end.is_fault() {
+        return Err(format!("{} on frame {}", result.end, result.frames));
+    }
+    let display = sys.display.read().unwrap().clone();
+    let pbm = rom.with_extension("pbm");
+    let golden = if pbm.is_file() {
+        pbm
+    } else {
+        rom.with_extension("txt")
+    };
+    if bless {
+        let contents = match golden.extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => screenshot::to_pbm(&display, 1),
+            _ => screenshot::to_ascii(&display, 1),
+        };
+        fs::write(&golden, contents)
+            .map_err(|err| format!("cannot write {}: {}", golden.display(), err))?;
+        return Ok(format!(" (blessed {})", golden.display()));
+    }
+    if !golden.is_file() {
+        return Err("no golden screen, run with --bless to create it".to_string());
+    }
+    let expected = load_screen(&golden)?;
+    let actual = screen_of(&display);
+    let differences = differences(&expected, &actual);
+    if differences > 0 {
+        return Err(format!(
+            "{} pixels differ from {} after {} frames ('+' only now, '-' only in the golden)\n{}",
+            differences,
+            golden.display(),
+            result.frames,
+            diff(&expected, &actual)
+        ));
+    }
+    Ok(String::new())
+}
+
+fn screen_of(display: &Chip8Display) -> Screen {
+    (0..Chip8Display::HEIGHT)
+        .map(|y| {
+            (0..Chip8Display::WIDTH)
+                .map(|x| display.get_pixel(x, y))
+                .collect()
+        })
+        .collect()
+}
+
+//Golden screen in either format, it has to be the size of the display
+fn load_screen(path: &Path) -> Result<Screen, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let screen = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pbm") => parse_pbm(&contents),
+        _ => Ok(contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.chars().map(|c| c == '#').collect())
+            .collect()),
+    }
+    .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let is_display_size = screen.len() == Chip8Display::HEIGHT
+        && screen.iter().all(|row| row.len() == Chip8Display::WIDTH);
+    if !is_display_size {
+        return Err(format!(
+            "{} is not {}x{} pixels",
+            path.display(),
+            Chip8Display::WIDTH,
+            Chip8Display::HEIGHT
+        ));
+    }
+    Ok(screen)
+}
+
+//Plain (P1) PBM, comments allowed
+fn parse_pbm(contents: &str) -> Result<Screen, String> {
+    let mut tokens = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split_whitespace());
+    if tokens.next() != Some("P1") {
+        return Err("not a plain PBM file".to_string());
+    }
+    let mut size = || {
+        tokens
+            .next()
+            .and_then(|token| token.parse::<usize>().ok())
+            .ok_or("invalid PBM size")
+    };
+    let (width, height) = (size()?, size()?);
+    let pixels = tokens
+        .flat_map(|token| token.chars())
+        .map(|c| c == '1')
+        .collect::<Vec<bool>>();
+    if pixels.len() != width * height {
+        return Err(format!("expected {} pixels", width * height));
+    }
+    Ok(pixels
+        .chunks(width.max(1))
+        .map(|row| row.to_vec())
+        .collect())
+}
+
+fn differences(expected: &Screen, actual: &Screen) -> usize {
+    expected
+        .iter()
+        .flatten()
+        .zip(actual.iter().flatten())
+        .filter(|(expected, actual)| expected != actual)
+        .count()
+}
+
+fn diff(expected: &Screen, actual: &Screen) -> String {
+    expected
+        .iter()
+        .zip(actual.iter())
+        .map(|(expected, actual)| {
+            expected
+                .iter()
+                .zip(actual.iter())
+                .map(|(expected, actual)| match (expected, actual) {
+                    (true, true) => '#',
+                    (false, true) => '+',
+                    (true, false) => '-',
+                    (false, false) => '.',
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Golden screen of display written in the format of extension, as --bless does
+    fn golden(display: &Chip8Display, extension: &str) -> Screen {
+        let path =
+            std::env::temp_dir().join(format!("chip8-golden-{}.{}", std::process::id(), extension));
+        let contents = match extension {
+            "pbm" => screenshot::to_pbm(display, 1),
+            _ => screenshot::to_ascii(display, 1),
+        };
+        fs::write(&path, contents).unwrap();
+        let screen = load_screen(&path);
+        fs::remove_file(&path).unwrap();
+        screen.unwrap()
+    }
+
+    #[test]
+    fn goldens_match_the_screen_they_were_written_from() {
+        let mut display = Chip8Display::new();
+        display.set_pixel(0, 0);
+        display.set_pixel(10, 7);
+        display.set_pixel(63, 31);
+        for extension in ["pbm", "txt"].iter() {
+            assert_eq!(
+                differences(&golden(&display, extension), &screen_of(&display)),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn a_single_pixel_difference_is_reported() {
+        let mut display = Chip8Display::new();
+        display.set_pixel(10, 7);
+        for extension in ["pbm", "txt"].iter() {
+            let expected = golden(&display, extension);
+            let mut changed = display.clone();
+            changed.set_pixel(11, 7);
+            let actual = screen_of(&changed);
+            assert_eq!(differences(&expected, &actual), 1);
+            let diff = diff(&expected, &actual);
+            assert_eq!(diff.lines().nth(7).unwrap().find('+'), Some(11));
+            assert_eq!(diff.matches(['+', '-']).count(), 1);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::chip8::display::Chip8Display;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crossterm::cursor::MoveTo;
+use crossterm::QueueableCommand;
+use std::env;
+use std::io::{self, Write};
+use tui::layout::Rect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    // DEC sixel bitmaps, xterm -ti vt340, mlterm, foot, WezTerm...
+    Sixel,
+
+    // Kitty graphics protocol, kitty, WezTerm, Ghostty...
+    Kitty,
+}
+
+//Draws the screen as a real bitmap over the cells tui left empty, the image is only sent again
+//when the picture, the palette or the area changes
+pub struct GraphicsOutput {
+    // Protocol, area, phosphor generation and palette of the image on screen
+    drawn: Option<(GraphicsProtocol, Rect, u64, Palette)>,
+}
+
+impl GraphicsProtocol {
+    // Used when the terminal can't tell us its cell size in pixels
+    const DEFAULT_CELL_SIZE: (u16, u16) = (10, 20);
+
+    // Kitty expects the base64 payload in chunks of at most 4096 bytes
+    const KITTY_CHUNK: usize = 4096;
+    const KITTY_IMAGE_ID: u32 = 8;
+
+    pub fn from_name(name: &str) -> Option<GraphicsProtocol> {
+        match name {
+            "sixel" => Some(GraphicsProtocol::Sixel),
+            "kitty" => Some(GraphicsProtocol::Kitty),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GraphicsProtocol::Sixel => "sixel",
+            GraphicsProtocol::Kitty => "kitty",
+        }
+    }
+
+    //Terminals don't answer queries reliably through the event reader, so like COLORTERM for the palettes
+    //support is guessed from the environment. CHIP8_GRAPHICS=sixel|kitty|none overrides the guess.
+    //iTerm2 only shows sixel in some versions and settings, it gets text unless CHIP8_GRAPHICS=sixel asks for it
+    pub fn detect() -> Option<GraphicsProtocol> {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+        if let Ok(name) = env::var("CHIP8_GRAPHICS") {
+            return GraphicsProtocol::from_name(&name);
+        }
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || env::var("KITTY_WINDOW_ID").is_ok()
+            || ["WezTerm", "ghostty"].contains(&program.as_str())
+        {
+            Some(GraphicsProtocol::Kitty)
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || program == "mlterm"
+        {
+            Some(GraphicsProtocol::Sixel)
+        } else {
+            None
+        }
+    }
+
+    //Size of a terminal cell in pixels from the window size the tty reports
+    #[cfg(unix)]
+    fn cell_size() -> (u16, u16) {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if res != 0 || size.ws_col == 0 || size.ws_row == 0 || size.ws_xpixel == 0 {
+            return GraphicsProtocol::DEFAULT_CELL_SIZE;
+        }
+        (size.ws_xpixel / size.ws_col, size.ws_ypixel / size.ws_row)
+    }
+
+    #[cfg(not(unix))]
+    fn cell_size() -> (u16, u16) {
+        GraphicsProtocol::DEFAULT_CELL_SIZE
+    }
+
+    //Largest whole pixel scale that fits the screen into area
+    fn scale(area: Rect) -> usize {
+        let (cell_width, cell_height) = GraphicsProtocol::cell_size();
+        let width = area.width as usize * cell_width as usize / Chip8Display::WIDTH;
+        let height = area.height as usize * cell_height as usize / Chip8Display::HEIGHT;
+        width.min(height).max(1)
+    }
+
+    //Colour of every pixel of the scaled image, fading pixels get a handful of shades like the braille renderer
+    fn image(phosphor: &Phosphor, palette: &Palette, scale: usize) -> Vec<Vec<(u8, u8, u8)>> {
+        let levels = phosphor.levels();
+        (0..Chip8Display::HEIGHT * scale)
+            .map(|y| {
+                (0..Chip8Display::WIDTH * scale)
+                    .map(|x| {
+                        let level = levels[y / scale][x / scale];
+                        let level = match level {
+                            0 => 0,
+                            level => (level / 64 + 1).saturating_mul(64),
+                        };
+                        palette.shade_rgb(level)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn encode(self, image: &[Vec<(u8, u8, u8)>]) -> io::Result<Vec<u8>> {
+        match self {
+            GraphicsProtocol::Sixel => Ok(GraphicsProtocol::encode_sixel(image)),
+            GraphicsProtocol::Kitty => GraphicsProtocol::encode_kitty(image),
+        }
+    }
+
+    //Colour registers for every colour used, then bands of six rows, one pass per colour in a band
+    //with runs of the same sixel written as !count
+    fn encode_sixel(image: &[Vec<(u8, u8, u8)>]) -> Vec<u8> {
+        let height = image.len();
+        let width = image.first().map_or(0, Vec::len);
+        let mut colors: Vec<(u8, u8, u8)> = Vec::new();
+        for color in image.iter().flatten() {
+            if !colors.contains(color) {
+                colors.push(*color);
+            }
+        }
+        let mut res = format!("\x1bPq\"1;1;{};{}", width, height);
+        for (index, (r, g, b)) in colors.iter().enumerate() {
+            let percent = |channel: u8| channel as u32 * 100 / 255;
+            res += &format!(
+                "#{};2;{};{};{}",
+                index,
+                percent(*r),
+                percent(*g),
+                percent(*b)
+            );
+        }
+        for band in (0..height).step_by(6) {
+            for (index, color) in colors.iter().enumerate() {
+                let sixels = (0..width)
+                    .map(|x| {
+                        (0..6)
+                            .filter(|row| band + row < height && image[band + row][x] == *color)
+                            .fold(0, |acc, row| acc | 1 << row)
+                    })
+                    .collect::<Vec<u8>>();
+                if sixels.iter().all(|sixel| *sixel == 0) {
+                    continue;
+                }
+                res += &format!("#{}", index);
+                let mut x = 0;
+                while x < width {
+                    let run = sixels[x..].iter().take_while(|s| **s == sixels[x]).count();
+                    let c = (63 + sixels[x]) as char;
+                    if run > 3 {
+                        res += &format!("!{}{}", run, c);
+                    } else {
+                        res.extend(std::iter::repeat_n(c, run));
+                    }
+                    x += run;
+                }
+                res.push('$');
+            }
+            res.push('-');
+        }
+        res += "\x1b\\";
+        res.into_bytes()
+    }
+
+    //The image as a PNG sent in base64 chunks and shown at its own size, the cursor stays where it was
+    fn encode_kitty(image: &[Vec<(u8, u8, u8)>]) -> io::Result<Vec<u8>> {
+        let height = image.len();
+        let width = image.first().map_or(0, Vec::len);
+        let data = image
+            .iter()
+            .flatten()
+            .flat_map(|(r, g, b)| [*r, *g, *b])
+            .collect::<Vec<u8>>();
+        let mut png_data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_data, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)?;
+
+        let payload = STANDARD.encode(png_data);
+        let chunks = payload
+            .as_bytes()
+            .chunks(GraphicsProtocol::KITTY_CHUNK)
+            .collect::<Vec<&[u8]>>();
+        let mut res = Vec::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let more = (index + 1 < chunks.len()) as u8;
+            if index == 0 {
+                write!(
+                    res,
+                    "\x1b_Ga=T,f=100,i={},C=1,q=2,m={};",
+                    GraphicsProtocol::KITTY_IMAGE_ID,
+                    more
+                )?;
+            } else {
+                write!(res, "\x1b_Gm={};", more)?;
+            }
+            res.extend_from_slice(chunk);
+            res.extend_from_slice(b"\x1b\\");
+        }
+        Ok(res)
+    }
+
+    //Sixels are pixels in the cells and go with the next full redraw, kitty images have to be deleted
+    fn erase<W: Write>(self, out: &mut W) -> io::Result<()> {
+        if self == GraphicsProtocol::Kitty {
+            write!(
+                out,
+                "\x1b_Ga=d,d=I,i={},q=2\x1b\\",
+                GraphicsProtocol::KITTY_IMAGE_ID
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl GraphicsOutput {
+    pub fn new() -> GraphicsOutput {
+        GraphicsOutput { drawn: None }
+    }
+
+    pub fn is_drawn(&self) -> bool {
+        self.drawn.is_some()
+    }
+
+    pub fn area(&self) -> Option<Rect> {
+        self.drawn.map(|(_, area, ..)| area)
+    }
+
+    //Call after tui has drawn the frame, area is the inside of the screen block
+    pub fn draw<W: Write>(
+        &mut self,
+        out: &mut W,
+        protocol: GraphicsProtocol,
+        area: Rect,
+        phosphor: &Phosphor,
+        palette: &Palette,
+    ) -> io::Result<()> {
+        let state = (protocol, area, phosphor.generation(), *palette);
+        if self.drawn == Some(state) || area.width == 0 || area.height == 0 {
+            return Ok(());
+        }
+        let image = GraphicsProtocol::image(phosphor, palette, GraphicsProtocol::scale(area));
+        if let Some((drawn, ..)) = self.drawn {
+            drawn.erase(out)?;
+        }
+        out.queue(MoveTo(area.x, area.y))?;
+        out.write_all(&protocol.encode(&image)?)?;
+        out.flush()?;
+        self.drawn = Some(state);
+        Ok(())
+    }
+
+    //Removes the image, the caller clears the terminal so tui redraws the cells under it
+    pub fn erase<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if let Some((protocol, ..)) = self.drawn.take() {
+            protocol.erase(out)?;
+        }
+        out.flush()
+    }
+}
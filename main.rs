@@ -1,33 +1,189 @@
 mod app;
 mod chip8;
+mod cli;
+mod golden;
+mod graphics;
+mod palette;
+mod phosphor;
+mod renderer;
+mod rom_browser;
+mod rom_database;
+mod screenshot;
+mod settings;
+mod view_layout;
 use crate::chip8::thread_messages::Chip8ControlMessage;
 use app::App;
+use chip8::batch::{Chip8Batch, Chip8Halt};
+use chip8::clock::Chip8Speed;
+use chip8::config::Chip8Timing;
 use chip8::display::Chip8Display;
+use chip8::movie::Chip8Movie;
+use chip8::rom_loader::Chip8RomFormat;
+use chip8::Chip8;
+use cli::CliArgs;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use palette::Palette;
+use phosphor::PhosphorMode;
+use renderer::{RenderMode, Screen};
+use rom_browser::{BrowserEntryKind, RomBrowser};
 use std::{
     error::Error,
-    io,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc::channel;
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
+    layout::Rect,
+    style::Style,
     symbols,
     widgets::{
         canvas::{Canvas, Points},
-        Block, Borders, List, ListItem,
+        Block, Borders, Clear, List, ListItem, ListState,
     },
     Frame, Terminal,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let args = CliArgs::parse()?;
+    if args.bless && args.golden.is_none() {
+        return Err("--bless needs --golden".into());
+    }
+    //Golden runs don't need the app, each ROM gets a machine of its own
+    if let Some(dir) = &args.golden {
+        let failed = golden::run(Path::new(dir), args.bless)?;
+        if failed > 0 {
+            return Err(format!("{} ROMs failed", failed).into());
+        }
+        return Ok(());
+    }
+    let format = match &args.format {
+        Some(name) => Some(Chip8RomFormat::from_name(name).ok_or(format!(
+            "Unknown format {}, use bin, hex, ihex, zip or octo",
+            name
+        ))?),
+        None => None,
+    };
+    let mut app =
+        App::new(&args.rom, format).map_err(|err| format!("Cannot load {}: {}", args.rom, err))?;
+    if let Some(symbols) = &args.symbols {
+        app.sys.load_symbols(symbols)?;
+    }
+    if let Some(seed) = args.seed {
+        app.sys.set_seed(seed);
+    }
+    if let Some(name) = &args.renderer {
+        app.render_mode = RenderMode::from_name(name).ok_or(format!(
+            "Unknown renderer {}, use braille, half, full, sixel, kitty or graphics",
+            name
+        ))?;
+        if name == "graphics" && app.render_mode == RenderMode::Braille {
+            app.status = "No sixel or kitty graphics support detected, using braille".to_string();
+        }
+    }
+    if let Some(name) = &args.palette {
+        let palette = Palette::from_name(name).ok_or(format!(
+            "Unknown palette {}, use one of {}",
+            name,
+            Palette::names().join(", ")
+        ))?;
+        app.palette_override = Some(palette);
+        app.palette = palette;
+    }
+    if let Some(name) = &args.timing {
+        let timing = Chip8Timing::from_name(name)
+            .ok_or(format!("Unknown timing {}, use instructions or vip", name))?;
+        app.timing_override = Some(timing);
+        app.sys.config.write().unwrap().timing = timing;
+    }
+    if let Some(name) = &args.speed {
+        let speed = Chip8Speed::from_name(name).ok_or(format!(
+            "Unknown speed {}, use a multiplier from 0.1 to 100 or turbo",
+            name
+        ))?;
+        app.sys.clock.write().unwrap().speed = speed;
+    }
+    if let Some(name) = &args.phosphor {
+        app.phosphor.mode = PhosphorMode::from_name(name).ok_or(format!(
+            "Unknown phosphor mode {}, use off, fade or blend",
+            name
+        ))?;
+    }
+    if let Some(decay) = args.decay {
+        app.phosphor.decay = decay;
+    }
+    if let Some(scale) = args.scale {
+        app.screenshot_scale = scale;
+    }
+    for location in args.breakpoints.iter() {
+        app.sys.add_breakpoint(location)?;
+    }
+
+    if args.disassemble {
+        app.sys.disassemble(&args.rom, format)?;
+        return Ok(());
+    }
+
+    if let Some(dir) = &args.dump_sprites {
+        let count = app.sys.export_sprites(Path::new(dir))?;
+        println!("Wrote {} sprites to {}", count, dir);
+        return Ok(());
+    }
+
+    let movie = match &args.play {
+        Some(filename) => Some(Chip8Movie::from_bytes(&fs::read(filename)?)?),
+        None => None,
+    };
+    if args.headless && movie.is_none() {
+        return run_batch(&args, &mut app);
+    }
+    if args.headless {
+        let movie = movie.unwrap();
+        let frames = movie.frames;
+        let final_hash = movie.final_hash;
+        app.sys = Chip8::with_program(&args.rom, &app.rom_program);
+        app.sys.start_playback(movie);
+        if let Some(filename) = &args.gif {
+            app.gif_path = Some(PathBuf::from(filename));
+            app.start_gif();
+        }
+        app.sys.run_playback();
+        let hash = app.sys.snapshot().hash();
+        println!("Replayed {} frames, state hash {:016X}", frames, hash);
+        if args.gif.is_some() {
+            app.stop_gif();
+            println!("{}", app.status);
+        }
+        if hash != final_hash {
+            return Err(format!(
+                "State hash does not match the recording ({:016X})",
+                final_hash
+            )
+            .into());
+        }
+        return Ok(());
+    }
+    if let Some(movie) = movie {
+        app.sys.load_program_reset(&args.rom, &app.rom_program);
+        app.sys.start_playback(movie);
+    } else if let Some(filename) = &args.record {
+        app.movie_path = PathBuf::from(filename);
+        app.start_recording();
+    }
+    if let Some(filename) = &args.gif {
+        app.gif_path = Some(PathBuf::from(filename));
+        app.start_gif();
+    }
+
+    app.add_recent();
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -35,11 +191,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // create app and run it
-    let tick_rate = Duration::from_millis(16);
-    let key_timeout = Duration::from_millis(250);
-    let app = App::new("roms/BRIX");
-    let res = run_app(&mut terminal, app, tick_rate, key_timeout).await;
+    // run it, redraw and key timeout intervals come from the settings
+    let res = run_app(&mut terminal, &mut app).await;
+    app.stop_recording();
+    app.stop_gif();
 
     // restore terminal
     disable_raw_mode()?;
@@ -57,32 +212,141 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn run_app<B: Backend>(
-    terminal: &mut Terminal<B>,
-    app: App,
-    tick_rate: Duration, // this defines when the "display" should be redrawn
-    key_timeout: Duration,
-) -> io::Result<()> {
+//Headless run without a movie, see chip8/batch.rs. Faults exit with code 2 once the results are written
+fn run_batch(args: &CliArgs, app: &mut App) -> Result<(), Box<dyn Error>> {
+    let mut batch = Chip8Batch::new();
+    if let Some(frames) = args.frames {
+        batch.frames = frames;
+    } else if args.cycles.is_some() {
+        batch.frames = u64::MAX;
+    }
+    batch.instructions = args.cycles;
+    for name in args.until.iter() {
+        batch.halts.push(Chip8Halt::from_name(name).ok_or(format!(
+            "Unknown halt condition {}, use loop, exit or key",
+            name
+        ))?);
+    }
+    if let Some(filename) = &args.keys {
+        batch.keys = Chip8Batch::parse_keys(&fs::read_to_string(filename)?)
+            .map_err(|err| format!("{}: {}", filename, err))?;
+    }
+    if let Some(filename) = &args.gif {
+        app.gif_path = Some(PathBuf::from(filename));
+        app.start_gif();
+    }
+
+    let result = app.sys.run_batch(&batch);
+    let state = app.sys.snapshot();
+    let report = result.report(&state, &screenshot::to_ascii(&state.display, 1));
+    match &args.report {
+        Some(filename) => fs::write(filename, &report)?,
+        None => print!("{}", report),
+    }
+    if let Some(filename) = &args.screen {
+        let path = Path::new(filename);
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => fs::write(
+                path,
+                screenshot::to_png(&state.display, app.screenshot_scale, &app.palette)?,
+            )?,
+            Some("pbm") => fs::write(
+                path,
+                screenshot::to_pbm(&state.display, app.screenshot_scale),
+            )?,
+            _ => fs::write(path, screenshot::to_ascii(&state.display, 1))?,
+        }
+    }
+    if args.gif.is_some() {
+        app.stop_gif();
+        eprintln!("{}", app.status);
+    }
+    if result.end.is_fault() {
+        process::exit(2);
+    }
+    Ok(())
+}
+
+async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
     let mut last_tick = Instant::now();
     let mut last_key_press = Instant::now();
 
     let (timer_tx, timer_rx) = channel(1);
     let (cpu_tx, cpu_rx) = channel(1);
 
-    app.sys.start_timers_thread(timer_rx);
-    app.sys.start_cpu_thread(cpu_rx);
+    app.sys.start_clock_thread(cpu_rx, timer_rx);
 
     timer_tx.send(Chip8ControlMessage::Start).await.unwrap();
     cpu_tx.send(Chip8ControlMessage::Start).await.unwrap();
 
     loop {
-        terminal.draw(|f| ui(f, &app))?;
+        if let Some(browser) = app.browser.as_mut() {
+            browser.look_up(&app.database, RomBrowser::LOOK_UPS_PER_FRAME);
+        }
+        let frame = app.sys.timers.read().unwrap().frame;
+        //UI busy time covers the phosphor update and ui, not the terminal output after ui
+        let started = Instant::now();
+        app.phosphor.update(&app.sys.display.read().unwrap(), frame);
+        let mut ui_busy = started.elapsed();
+        terminal.draw(|f| {
+            let started = Instant::now();
+            ui(f, app);
+            ui_busy += started.elapsed();
+        })?;
+        app.ui_busy.add_duration(ui_busy);
+        app.frames.add(1);
+        match app.render_mode {
+            RenderMode::Graphics(protocol) if app.browser.is_none() => {
+                let area = Block::default()
+                    .borders(Borders::ALL)
+                    .inner(app.layout.split(terminal.size()?).0);
+                //Moved images leave pixels behind that only a full redraw removes
+                if app.graphics.area().is_some_and(|drawn| drawn != area) {
+                    app.graphics.erase(&mut io::stdout())?;
+                    terminal.clear()?;
+                }
+                app.graphics.draw(
+                    &mut io::stdout(),
+                    protocol,
+                    area,
+                    &app.phosphor,
+                    &app.palette,
+                )?;
+            }
+            //Left over pixels are wiped by redrawing everything
+            _ if app.graphics.is_drawn() => {
+                app.graphics.erase(&mut io::stdout())?;
+                terminal.clear()?;
+            }
+            _ => {}
+        }
+        let is_playing = app.sys.is_playing();
+        let tick_rate = app.tick_rate; // this defines when the "display" should be redrawn
 
         let timeout = tick_rate
             .checked_sub(last_tick.elapsed())
             .unwrap_or_else(|| Duration::from_secs(0));
         if event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
+                if app.browser.is_some() {
+                    app.browser_key(key.code);
+                    continue;
+                }
+                //Keys in the key map go to the keypad, with Alt held they are hotkeys like any other key
+                if !key.modifiers.contains(KeyModifiers::ALT) {
+                    if let Some(keypad_key) = app.key_bindings.get(&key.code) {
+                        // Keypad input comes from the movie while one is playing
+                        if !is_playing {
+                            app.sys
+                                .keyboard
+                                .write()
+                                .unwrap()
+                                .set_pending_key(*keypad_key);
+                        }
+                        last_key_press = Instant::now();
+                        continue;
+                    }
+                }
                 match key.code {
                     KeyCode::Char('q') => {
                         return Ok(());
@@ -95,6 +359,7 @@ async fn run_app<B: Backend>(
                         timer_tx.send(Chip8ControlMessage::Stop).await.unwrap();
                     }
                     KeyCode::Char('c') => {
+                        timer_tx.send(Chip8ControlMessage::Start).await.unwrap();
                         cpu_tx.send(Chip8ControlMessage::Start).await.unwrap();
                     }
                     KeyCode::Char('d') => {
@@ -103,20 +368,81 @@ async fn run_app<B: Backend>(
                     KeyCode::Char('n') => {
                         cpu_tx.send(Chip8ControlMessage::Step).await.unwrap();
                     }
+                    KeyCode::Char('.') => {
+                        cpu_tx.send(Chip8ControlMessage::Frame).await.unwrap();
+                    }
+                    KeyCode::Char(']') => {
+                        let speed = app.sys.clock.read().unwrap().speed.faster();
+                        app.set_speed(speed);
+                    }
+                    KeyCode::Char('[') => {
+                        let speed = app.sys.clock.read().unwrap().speed.slower();
+                        app.set_speed(speed);
+                    }
+                    KeyCode::Char('\\') => {
+                        app.set_speed(Chip8Speed::NORMAL);
+                    }
                     KeyCode::Char('r') => {
-                        app.sys.load_file_reset("roms/PONG");
+                        app.open_browser();
+                    }
+                    KeyCode::Char('x') => {
+                        app.status = match app.sys.export_sprites(Path::new("sprites")) {
+                            Ok(count) => format!("Exported {} sprites to sprites/", count),
+                            Err(err) => format!("Sprite export failed: {}", err),
+                        };
+                    }
+                    KeyCode::F(5) => {
+                        app.quick_save();
+                    }
+                    KeyCode::F(9) => {
+                        app.quick_load();
+                    }
+                    KeyCode::Char(slot @ '0'..='9') => {
+                        app.save_slot = slot.to_digit(10).unwrap() as u8;
+                        app.status = format!("Save slot {}", app.save_slot);
                     }
-                    KeyCode::Down => {
-                        app.sys.keyboard.write().unwrap().set_key(0);
+                    KeyCode::Char('p') => {
+                        app.screenshot();
                     }
-                    KeyCode::Up => {
-                        app.sys.keyboard.write().unwrap().set_key(1);
+                    KeyCode::Char('v') => {
+                        app.render_mode = app.render_mode.next();
+                        app.status = format!("Renderer {}", app.render_mode.name());
                     }
-                    KeyCode::Right => {
-                        app.sys.keyboard.write().unwrap().set_key(2);
+                    KeyCode::Char('o') => {
+                        let palette = app.palette.next();
+                        app.palette_override = Some(palette);
+                        app.palette = palette;
+                        app.status = format!("Palette {}", palette.name);
                     }
-                    KeyCode::Left => {
-                        app.sys.keyboard.write().unwrap().set_key(3);
+                    KeyCode::Char('z') => {
+                        app.toggle_fullscreen();
+                    }
+                    KeyCode::Char('l') => {
+                        app.next_layout();
+                    }
+                    KeyCode::Char('+') => {
+                        app.resize_screen(5);
+                    }
+                    KeyCode::Char('-') => {
+                        app.resize_screen(-5);
+                    }
+                    KeyCode::Char('f') => {
+                        app.phosphor.mode = app.phosphor.mode.next();
+                        app.status = format!("Phosphor filter {}", app.phosphor.mode.name());
+                    }
+                    KeyCode::Char('g') => {
+                        if app.sys.gif.read().unwrap().is_some() {
+                            app.stop_gif();
+                        } else {
+                            app.start_gif();
+                        }
+                    }
+                    KeyCode::Char('m') => {
+                        if app.sys.recording.read().unwrap().is_some() {
+                            app.stop_recording();
+                        } else {
+                            app.start_recording();
+                        }
                     }
                     _ => {}
                 }
@@ -124,8 +450,8 @@ async fn run_app<B: Backend>(
             }
         }
 
-        if last_key_press.elapsed() >= key_timeout {
-            app.sys.keyboard.write().unwrap().reset_keys();
+        if last_key_press.elapsed() >= app.key_timeout && !is_playing {
+            app.sys.keyboard.write().unwrap().reset_pending_keys();
         }
         if last_tick.elapsed() >= tick_rate {
             last_tick = Instant::now();
@@ -134,43 +460,120 @@ async fn run_app<B: Backend>(
 }
 
 fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
-    let list_coords = app.sys.display.read().unwrap().get_set_pixel_coords();
-    let chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
-        .split(f.size());
-    let canvas = Canvas::default()
-        .marker(symbols::Marker::Braille)
-        .block(Block::default().borders(Borders::ALL))
-        .paint(|ctx| {
-            ctx.draw(&Points {
-                coords: &list_coords,
-                color: Color::White,
-            });
-        })
-        .x_bounds([0.0, Chip8Display::WIDTH as f64])
-        .y_bounds([0.0, Chip8Display::HEIGHT as f64]);
-    f.render_widget(canvas, chunks[0]);
+    let (screen_area, debug_area) = app.layout.split(f.size());
+    let block = Block::default()
+        .title(app.rom_title.as_deref().unwrap_or(&app.rom).to_string())
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(app.palette.ui_color()));
+    match app.render_mode {
+        RenderMode::Braille => {
+            let shades = app.phosphor.coords();
+            let canvas = Canvas::default()
+                .marker(symbols::Marker::Braille)
+                .background_color(app.palette.off_color())
+                .block(block)
+                .paint(|ctx| {
+                    for (level, coords) in shades.iter() {
+                        ctx.draw(&Points {
+                            coords,
+                            color: app.palette.shade(*level),
+                        });
+                    }
+                })
+                .x_bounds([0.0, Chip8Display::WIDTH as f64])
+                .y_bounds([0.0, Chip8Display::HEIGHT as f64]);
+            f.render_widget(canvas, screen_area);
+        }
+        //The bitmap goes over the empty inside after the frame is drawn
+        RenderMode::Graphics(_) => {
+            let inner = block.inner(screen_area);
+            f.render_widget(block, screen_area);
+            f.render_widget(
+                Block::default().style(Style::default().bg(app.palette.off_color())),
+                inner,
+            );
+        }
+        mode => {
+            let inner = block.inner(screen_area);
+            f.render_widget(block, screen_area);
+            f.render_widget(
+                Screen {
+                    levels: app.phosphor.levels(),
+                    mode,
+                    palette: &app.palette,
+                },
+                inner,
+            );
+        }
+    }
+    if let Some(debug_area) = debug_area {
+        debug_ui(f, app, debug_area);
+    }
+    if let Some(browser) = &app.browser {
+        browser_ui(f, browser, &app.palette);
+    }
+}
+
+//Measured over the last seconds next to what the settings ask for, which turbo leaves open
+fn performance_items(app: &App, speed: Chip8Speed) -> Vec<ListItem<'static>> {
+    let config = *app.sys.config.read().unwrap();
+    let stats = app.sys.stats.read().unwrap();
+    let expected = |hz: u32| match speed {
+        Chip8Speed::Percent(percent) => format!(" of {}", hz as u64 * percent as u64 / 100),
+        Chip8Speed::Turbo => String::new(),
+    };
+    let expected_ips = match config.timing {
+        Chip8Timing::Instructions => expected(config.cpu_hz),
+        Chip8Timing::Vip => String::new(),
+    };
+    vec![
+        ListItem::new(format!(
+            "Instructions/s: {:.0}{}",
+            stats.instructions.rate(),
+            expected_ips
+        )),
+        ListItem::new(format!(
+            "Timer ticks/s: {:.1}{}",
+            stats.timer_ticks.rate(),
+            expected(config.timer_hz)
+        )),
+        ListItem::new(format!("Frames drawn/s: {:.1}", app.frames.rate())),
+        ListItem::new(format!(
+            "CPU busy {:.1}% / UI busy {:.1}%",
+            stats.cpu_busy.busy_percent(),
+            app.ui_busy.busy_percent()
+        )),
+    ]
+}
+
+fn debug_ui<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
     let instructions = app.sys.instructions.read().unwrap();
     let mut items = instructions
         .iter()
         .map(|i| ListItem::new(i.to_owned()))
         .collect::<Vec<ListItem>>();
 
-    items.push(ListItem::new(format!(" ")));
+    items.push(ListItem::new(" ".to_string()));
     let registers = app.sys.registers.read().unwrap();
     items.push(ListItem::new(format!("General: {:02X?}", registers.genral)));
+    let symbols = app.sys.symbols.read().unwrap();
+    let call_stack = registers.stack
+        [..(registers.stack_pointer as usize).min(registers.stack.len())]
+        .iter()
+        .map(|address| symbols.format_address(*address))
+        .collect::<Vec<String>>();
     items.push(ListItem::new(format!(
-        "Stack: {:02X?} : {:02X?}",
-        registers.stack, registers.stack_pointer
+        "Stack: {} : {:02X?}",
+        call_stack.join(" > "),
+        registers.stack_pointer
     )));
     items.push(ListItem::new(format!(
         "I: {:#05X}",
         registers.memory_address
     )));
     items.push(ListItem::new(format!(
-        "PC: {:#05X}",
-        registers.program_counter
+        "PC: {}",
+        symbols.format_address(registers.program_counter)
     )));
     items.push(ListItem::new(format!("Special:{:02X?}", registers.special)));
     let keyboard = app.sys.keyboard.read().unwrap();
@@ -178,9 +581,100 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         "Keyboard: {:#016b}",
         keyboard.get_key_map()
     )));
+    let breakpoints = app.sys.breakpoints.read().unwrap();
+    let breakpoint_list = breakpoints
+        .iter()
+        .map(|address| symbols.format_address(*address))
+        .collect::<Vec<String>>();
+    items.push(ListItem::new(format!(
+        "Breakpoints: {}",
+        breakpoint_list.join(", ")
+    )));
+    if let Some(address) = breakpoints.hit {
+        items.push(ListItem::new(format!(
+            "Stopped at breakpoint {}",
+            symbols.format_address(address)
+        )));
+    }
+    if let Some(info) = &app.rom_info {
+        items.push(ListItem::new(format!(
+            "ROM: {} by {} ({})",
+            info.title,
+            info.authors.join(", "),
+            info.platform.as_deref().unwrap_or("unknown platform")
+        )));
+    }
+    items.push(ListItem::new(format!("Save slot: {}", app.save_slot)));
+    let timing = app.sys.config.read().unwrap().timing;
+    let clock = app.sys.clock.read().unwrap();
+    items.push(ListItem::new(match timing {
+        Chip8Timing::Instructions => {
+            format!("Frame: {} / {} instructions", clock.used, clock.budget)
+        }
+        Chip8Timing::Vip => format!(
+            "Frame: {} / {} VIP machine cycles",
+            clock.used, clock.budget
+        ),
+    }));
+    items.push(ListItem::new(format!("Speed: {}", clock.speed.name())));
+    let speed = clock.speed;
+    drop(clock);
+    items.extend(performance_items(app, speed));
+    let rng = app.sys.rng.read().unwrap();
+    items.push(ListItem::new(format!("RNG seed {:#X}", rng.seed())));
+    drop(rng);
+    let frame = app.sys.timers.read().unwrap().frame;
+    if app.sys.recording.read().unwrap().is_some() {
+        items.push(ListItem::new(format!("Recording movie, frame {}", frame)));
+    }
+    if let Some(movie) = app.sys.playback.read().unwrap().as_ref() {
+        items.push(ListItem::new(format!(
+            "Playing movie, frame {} / {}",
+            frame, movie.frames
+        )));
+    }
+    items.push(ListItem::new(app.status.clone()));
 
     let list = List::new(items)
         .block(Block::default().title("Debug Info").borders(Borders::ALL))
-        .style(Style::default().fg(Color::White));
-    f.render_widget(list, chunks[1]);
+        .style(Style::default().fg(app.palette.ui_color()));
+    f.render_widget(list, area);
+}
+
+//ROM browser drawn over the middle of the screen
+fn browser_ui<B: Backend>(f: &mut Frame<B>, browser: &RomBrowser, palette: &Palette) {
+    let size = f.size();
+    let area = Rect::new(
+        size.width / 10,
+        size.height / 10,
+        size.width * 8 / 10,
+        size.height * 8 / 10,
+    );
+    let items = browser
+        .entries
+        .iter()
+        .map(|entry| match entry.kind {
+            BrowserEntryKind::Recent => ListItem::new(format!("* {}", entry.label())),
+            _ => ListItem::new(format!("  {}", entry.label())),
+        })
+        .collect::<Vec<ListItem>>();
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(format!(
+                    "Load ROM from {} (* recent, Enter load, Backspace up, Esc close)",
+                    browser.dir.display()
+                ))
+                .borders(Borders::ALL),
+        )
+        .style(Style::default().fg(palette.ui_color()))
+        .highlight_style(
+            Style::default()
+                .fg(palette.color((0, 0, 0)))
+                .bg(palette.ui_color()),
+        );
+    let mut state = ListState::default();
+    state.select(Some(browser.selected));
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut state);
 }
@@ -0,0 +1,176 @@
+use std::env;
+use tui::style::Color;
+
+type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub on: Rgb,
+    pub off: Rgb,
+
+    // Second plane and both planes lit, for multi-plane (XO-CHIP) screens
+    pub plane2: Rgb,
+    pub overlap: Rgb,
+
+    // Text and borders around the screen
+    pub ui: Rgb,
+
+    // Without 24 bit colour the terminal gets the nearest of the 16 ANSI colours
+    pub true_color: bool,
+}
+
+impl Palette {
+    // name, on, off, plane2, overlap, ui
+    const NAMED: [(&'static str, Rgb, Rgb, Rgb, Rgb, Rgb); 6] = [
+        (
+            "classic",
+            (0xFF, 0xFF, 0xFF),
+            (0x00, 0x00, 0x00),
+            (0xAA, 0xAA, 0xAA),
+            (0x55, 0x55, 0x55),
+            (0xFF, 0xFF, 0xFF),
+        ),
+        (
+            "green",
+            (0x33, 0xFF, 0x66),
+            (0x00, 0x14, 0x00),
+            (0x1A, 0x99, 0x33),
+            (0x99, 0xFF, 0xAA),
+            (0x33, 0xFF, 0x66),
+        ),
+        (
+            "amber",
+            (0xFF, 0xB0, 0x00),
+            (0x1A, 0x0F, 0x00),
+            (0xA0, 0x6A, 0x00),
+            (0xFF, 0xD4, 0x80),
+            (0xFF, 0xB0, 0x00),
+        ),
+        (
+            "lcd",
+            (0x0F, 0x38, 0x0F),
+            (0x9B, 0xBC, 0x0F),
+            (0x30, 0x62, 0x30),
+            (0x8B, 0xAC, 0x0F),
+            (0x9B, 0xBC, 0x0F),
+        ),
+        (
+            "high-contrast",
+            (0xFF, 0xFF, 0x00),
+            (0x00, 0x00, 0x00),
+            (0x00, 0xFF, 0xFF),
+            (0xFF, 0xFF, 0xFF),
+            (0xFF, 0xFF, 0xFF),
+        ),
+        (
+            "octo",
+            (0xFF, 0xCC, 0x00),
+            (0x99, 0x66, 0x00),
+            (0xFF, 0x66, 0x00),
+            (0x66, 0x22, 0x00),
+            (0xFF, 0xCC, 0x00),
+        ),
+    ];
+
+    // Colours xterm uses for the 16 ANSI colours
+    const ANSI: [(Color, Rgb); 16] = [
+        (Color::Black, (0x00, 0x00, 0x00)),
+        (Color::Red, (0xCD, 0x00, 0x00)),
+        (Color::Green, (0x00, 0xCD, 0x00)),
+        (Color::Yellow, (0xCD, 0xCD, 0x00)),
+        (Color::Blue, (0x00, 0x00, 0xEE)),
+        (Color::Magenta, (0xCD, 0x00, 0xCD)),
+        (Color::Cyan, (0x00, 0xCD, 0xCD)),
+        (Color::Gray, (0xE5, 0xE5, 0xE5)),
+        (Color::DarkGray, (0x7F, 0x7F, 0x7F)),
+        (Color::LightRed, (0xFF, 0x00, 0x00)),
+        (Color::LightGreen, (0x00, 0xFF, 0x00)),
+        (Color::LightYellow, (0xFF, 0xFF, 0x00)),
+        (Color::LightBlue, (0x5C, 0x5C, 0xFF)),
+        (Color::LightMagenta, (0xFF, 0x00, 0xFF)),
+        (Color::LightCyan, (0x00, 0xFF, 0xFF)),
+        (Color::White, (0xFF, 0xFF, 0xFF)),
+    ];
+
+    pub fn new() -> Palette {
+        Palette::from_name("classic").unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        Palette::NAMED.iter().find(|named| named.0 == name).map(
+            |(name, on, off, plane2, overlap, ui)| Palette {
+                name,
+                on: *on,
+                off: *off,
+                plane2: *plane2,
+                overlap: *overlap,
+                ui: *ui,
+                true_color: Palette::detect_true_color(),
+            },
+        )
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        Palette::NAMED.iter().map(|named| named.0).collect()
+    }
+
+    //The named palette after this one, custom palettes go back to the first
+    pub fn next(&self) -> Palette {
+        let index = Palette::NAMED
+            .iter()
+            .position(|named| named.0 == self.name)
+            .map_or(0, |index| (index + 1) % Palette::NAMED.len());
+        Palette::from_name(Palette::NAMED[index].0).unwrap()
+    }
+
+    //Terminals announce 24 bit colour through COLORTERM
+    fn detect_true_color() -> bool {
+        env::var("COLORTERM")
+            .map(|value| value.contains("truecolor") || value.contains("24bit"))
+            .unwrap_or(false)
+    }
+
+    pub fn color(&self, rgb: Rgb) -> Color {
+        if self.true_color {
+            return Color::Rgb(rgb.0, rgb.1, rgb.2);
+        }
+        let distance = |other: &Rgb| {
+            let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            channel(rgb.0, other.0) + channel(rgb.1, other.1) + channel(rgb.2, other.2)
+        };
+        Palette::ANSI
+            .iter()
+            .min_by_key(|(_, ansi)| distance(ansi))
+            .map(|(color, _)| *color)
+            .unwrap()
+    }
+
+    //Colour for each combination of lit planes: none, first, second, both
+    pub fn planes(&self) -> [Rgb; 4] {
+        [self.off, self.on, self.plane2, self.overlap]
+    }
+
+    pub fn off_color(&self) -> Color {
+        self.color(self.off)
+    }
+
+    //Between off (0) and on (255), for pixels that are fading out
+    pub fn shade(&self, level: u8) -> Color {
+        self.color(self.shade_rgb(level))
+    }
+
+    pub fn shade_rgb(&self, level: u8) -> Rgb {
+        let mix =
+            |off: u8, on: u8| (off as i32 + (on as i32 - off as i32) * level as i32 / 255) as u8;
+        (
+            mix(self.off.0, self.on.0),
+            mix(self.off.1, self.on.1),
+            mix(self.off.2, self.on.2),
+        )
+    }
+
+    pub fn ui_color(&self) -> Color {
+        self.color(self.ui)
+    }
+}
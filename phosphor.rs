@@ -0,0 +1,166 @@
+use crate::chip8::display::Chip8Display;
+
+// Brightness of every pixel as drawn, 0 is off and 255 fully lit
+pub type Levels = [[u8; Chip8Display::WIDTH]; Chip8Display::HEIGHT];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhosphorMode {
+    // Pixels are drawn as they are in the framebuffer
+    Off,
+
+    // Pixels that go out fade over the decay frames like a CRT phosphor
+    Fade,
+
+    // A pixel lit in this frame or the one before is drawn lit
+    Blend,
+}
+
+//Filter between the framebuffer and the renderers against the flicker of XOR drawing,
+//it only reads the display so collisions still see the real framebuffer
+pub struct Phosphor {
+    pub mode: PhosphorMode,
+
+    // Frames a pixel takes to fade out in Fade mode
+    pub decay: u8,
+    levels: Levels,
+
+    // Framebuffer at the last update and at the frame before it
+    current: [[bool; Chip8Display::WIDTH]; Chip8Display::HEIGHT],
+    previous: [[bool; Chip8Display::WIDTH]; Chip8Display::HEIGHT],
+    last_frame: Option<u64>,
+
+    // Display generation and mode the levels were worked out for, none until the first update
+    display_generation: Option<u64>,
+    levels_mode: PhosphorMode,
+
+    // Canvas points for the braille renderer, only rebuilt when the levels change
+    coords: Vec<(u8, Vec<(f64, f64)>)>,
+
+    // Bumped whenever the levels change
+    generation: u64,
+}
+
+impl PhosphorMode {
+    pub fn from_name(name: &str) -> Option<PhosphorMode> {
+        match name {
+            "off" => Some(PhosphorMode::Off),
+            "fade" => Some(PhosphorMode::Fade),
+            "blend" => Some(PhosphorMode::Blend),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PhosphorMode::Off => "off",
+            PhosphorMode::Fade => "fade",
+            PhosphorMode::Blend => "blend",
+        }
+    }
+
+    pub fn next(self) -> PhosphorMode {
+        match self {
+            PhosphorMode::Off => PhosphorMode::Fade,
+            PhosphorMode::Fade => PhosphorMode::Blend,
+            PhosphorMode::Blend => PhosphorMode::Off,
+        }
+    }
+}
+
+impl Phosphor {
+    pub const DEFAULT_DECAY: u8 = 4;
+
+    pub fn new() -> Phosphor {
+        Phosphor {
+            mode: PhosphorMode::Off,
+            decay: Phosphor::DEFAULT_DECAY,
+            levels: [[0; Chip8Display::WIDTH]; Chip8Display::HEIGHT],
+            current: [[false; Chip8Display::WIDTH]; Chip8Display::HEIGHT],
+            previous: [[false; Chip8Display::WIDTH]; Chip8Display::HEIGHT],
+            last_frame: None,
+            display_generation: None,
+            levels_mode: PhosphorMode::Off,
+            coords: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    //Called before every redraw, pixels only fade when the emulated frame moves on. Only rows the
+    //display marked dirty are read unless the filter has to age every pixel
+    pub fn update(&mut self, display: &Chip8Display, frame: u64) {
+        let elapsed = match self.last_frame {
+            Some(last_frame) if frame >= last_frame => frame - last_frame,
+            _ => 1,
+        };
+        self.last_frame = Some(frame);
+        if elapsed > 0 {
+            self.previous = self.current;
+        }
+        let mut dirty = [false; Chip8Display::HEIGHT];
+        match self.display_generation {
+            Some(generation) if self.levels_mode == self.mode => {
+                display.dirty_rows(generation).for_each(|y| dirty[y] = true)
+            }
+            _ => dirty = [true; Chip8Display::HEIGHT],
+        }
+        let aging = self.mode != PhosphorMode::Off && elapsed > 0;
+        self.display_generation = Some(display.generation());
+        self.levels_mode = self.mode;
+
+        let step = (255 / self.decay.max(1) as u64 + 1).saturating_mul(elapsed);
+        let mut changed = false;
+        for (y, row) in self.levels.iter_mut().enumerate() {
+            if !dirty[y] && !aging {
+                continue;
+            }
+            for (x, level) in row.iter_mut().enumerate() {
+                if dirty[y] {
+                    self.current[y][x] = display.get_pixel(x, y);
+                }
+                let new_level = match self.mode {
+                    _ if self.current[y][x] => 255,
+                    PhosphorMode::Off => 0,
+                    PhosphorMode::Fade => (*level as u64).saturating_sub(step) as u8,
+                    PhosphorMode::Blend if self.previous[y][x] => 255,
+                    PhosphorMode::Blend => 0,
+                };
+                changed |= new_level != *level;
+                *level = new_level;
+            }
+        }
+        if changed {
+            self.coords = self.coords_by_level();
+            self.generation += 1;
+        }
+    }
+
+    pub fn levels(&self) -> &Levels {
+        &self.levels
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn coords(&self) -> &[(u8, Vec<(f64, f64)>)] {
+        &self.coords
+    }
+
+    //Lit pixels as canvas points (y going up) grouped by brightness, dimmest first
+    fn coords_by_level(&self) -> Vec<(u8, Vec<(f64, f64)>)> {
+        let mut res: Vec<(u8, Vec<(f64, f64)>)> = Vec::new();
+        for (y, row) in self.levels.iter().enumerate() {
+            for (x, level) in row.iter().enumerate().filter(|(_, level)| **level > 0) {
+                // A handful of shades is plenty for a terminal
+                let level = (*level / 64 + 1).saturating_mul(64);
+                let point = (x as f64, (Chip8Display::HEIGHT - y) as f64);
+                match res.iter_mut().find(|(shade, _)| *shade == level) {
+                    Some((_, points)) => points.push(point),
+                    None => res.push((level, vec![point])),
+                }
+            }
+        }
+        res.sort_by_key(|(level, _)| *level);
+        res
+    }
+}
@@ -0,0 +1,109 @@
+use crate::chip8::display::Chip8Display;
+use crate::graphics::GraphicsProtocol;
+use crate::palette::Palette;
+use crate::phosphor::Levels;
+
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::widgets::Widget;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    // 2x4 dots per cell through the tui canvas
+    Braille,
+
+    // Two square pixels per cell, ▀ with the top pixel in the foreground and the bottom one in the background
+    HalfBlock,
+
+    // One pixel per two columns
+    FullBlock,
+
+    // A bitmap drawn over the screen area by the terminal, see graphics.rs
+    Graphics(GraphicsProtocol),
+}
+
+//Draws the filtered display straight into the terminal buffer for the block modes, pixels are
+//sampled so the image fills whatever area it is given
+pub struct Screen<'a> {
+    pub levels: &'a Levels,
+    pub mode: RenderMode,
+    pub palette: &'a Palette,
+}
+
+impl RenderMode {
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name {
+            "braille" => Some(RenderMode::Braille),
+            "half" => Some(RenderMode::HalfBlock),
+            "full" => Some(RenderMode::FullBlock),
+            // Whichever protocol the terminal supports, braille without either
+            "graphics" => {
+                Some(GraphicsProtocol::detect().map_or(RenderMode::Braille, RenderMode::Graphics))
+            }
+            name => GraphicsProtocol::from_name(name).map(RenderMode::Graphics),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::Braille => "braille",
+            RenderMode::HalfBlock => "half",
+            RenderMode::FullBlock => "full",
+            RenderMode::Graphics(protocol) => protocol.name(),
+        }
+    }
+
+    //Graphics is only in the cycle when the terminal supports it
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Braille => RenderMode::HalfBlock,
+            RenderMode::HalfBlock => RenderMode::FullBlock,
+            RenderMode::FullBlock => GraphicsProtocol::detect()
+                .map(RenderMode::Graphics)
+                .unwrap_or(RenderMode::Braille),
+            RenderMode::Graphics(_) => RenderMode::Braille,
+        }
+    }
+
+    //Largest area inside area (borders included) that keeps pixels square, which is 4 columns per row
+    //for every mode since a terminal cell is about twice as tall as it is wide
+    pub fn fit(area: Rect) -> Rect {
+        let inner_width = area.width.saturating_sub(2);
+        let inner_height = area.height.saturating_sub(2);
+        let ratio = (Chip8Display::WIDTH / Chip8Display::HEIGHT * 2) as u16;
+        let width = inner_width.min(inner_height * ratio);
+        let height = width / ratio;
+        Rect::new(area.x, area.y, width + 2, height + 2)
+    }
+}
+
+impl Widget for Screen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.width == 0 || area.height == 0 {
+            return;
+        }
+        let shade = |x: usize, y: usize| self.palette.shade(self.levels[y][x]);
+        for row in 0..area.height {
+            for column in 0..area.width {
+                let x = column as usize * Chip8Display::WIDTH / area.width as usize;
+                let cell = buf.get_mut(area.x + column, area.y + row);
+                match self.mode {
+                    // Upper half in the foreground colour and lower half in the background colour
+                    RenderMode::HalfBlock => {
+                        let rows = area.height as usize * 2;
+                        let top = row as usize * 2 * Chip8Display::HEIGHT / rows;
+                        let bottom = (row as usize * 2 + 1) * Chip8Display::HEIGHT / rows;
+                        cell.set_symbol("▀")
+                            .set_fg(shade(x, top))
+                            .set_bg(shade(x, bottom));
+                    }
+                    _ => {
+                        let y = row as usize * Chip8Display::HEIGHT / area.height as usize;
+                        let color = shade(x, y);
+                        cell.set_symbol("█").set_fg(color).set_bg(color);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::chip8::rom_loader;
+use crate::rom_database::RomDatabase;
+use crate::settings::Settings;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserEntryKind {
+    Recent,
+    Parent,
+    Directory,
+    Rom,
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowserEntry {
+    pub kind: BrowserEntryKind,
+    pub path: PathBuf,
+    // Size and database title, filled in by RomBrowser::look_up a few entries at a time
+    pub size: Option<u64>,
+    pub title: Option<String>,
+    looked_up: bool,
+}
+
+//File picker shown over the screen, recently loaded ROMs are listed above the directory
+pub struct RomBrowser {
+    pub dir: PathBuf,
+    pub entries: Vec<BrowserEntry>,
+    pub selected: usize,
+}
+
+//Most recently loaded first, one path per line in recent-roms.txt in the user's config directory
+#[derive(Debug, Clone, Default)]
+pub struct RecentRoms {
+    pub paths: Vec<String>,
+}
+
+impl BrowserEntry {
+    fn new(kind: BrowserEntryKind, path: PathBuf) -> BrowserEntry {
+        BrowserEntry {
+            kind,
+            path,
+            size: None,
+            title: None,
+            looked_up: false,
+        }
+    }
+
+    //Reads and hashes the ROM, a ROM that can't be read keeps no size
+    fn look_up(&mut self, database: &RomDatabase) {
+        self.looked_up = true;
+        if let Ok(contents) = rom_loader::read(&self.path.to_string_lossy(), None) {
+            self.size = Some(contents.len() as u64);
+            self.title = database
+                .lookup(&Settings::rom_hash(&contents))
+                .map(|info| info.title.clone());
+        }
+    }
+
+    pub fn label(&self) -> String {
+        let name = match self.kind {
+            BrowserEntryKind::Recent => self.path.display().to_string(),
+            BrowserEntryKind::Parent => "..".to_string(),
+            _ => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        match self.kind {
+            BrowserEntryKind::Parent | BrowserEntryKind::Directory => format!("{}/", name),
+            _ => format!(
+                "{:<32} {:>7}  {}",
+                name,
+                self.size
+                    .map_or(String::new(), |size| format!("{} B", size)),
+                self.title.as_deref().unwrap_or("")
+            ),
+        }
+    }
+}
+
+impl RomBrowser {
+    const EXTENSIONS: [&'static str; 3] = ["ch8", "sc8", "xo8"];
+    // ROMs read and hashed each frame, so a large directory opens at once and fills in while shown
+    pub const LOOK_UPS_PER_FRAME: usize = 4;
+
+    pub fn new(dir: &Path, recent: &RecentRoms) -> RomBrowser {
+        let mut res = RomBrowser {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+            selected: 0,
+        };
+        res.refresh(recent);
+        res
+    }
+
+    //Lists the recent ROMs, then sub directories and ROMs of dir sorted by name
+    pub fn refresh(&mut self, recent: &RecentRoms) {
+        let mut entries = recent
+            .paths
+            .iter()
+            .map(|path| BrowserEntry::new(BrowserEntryKind::Recent, PathBuf::from(path)))
+            .collect::<Vec<BrowserEntry>>();
+        if self.dir.parent().is_some() {
+            entries.push(BrowserEntry::new(
+                BrowserEntryKind::Parent,
+                self.dir.join(".."),
+            ));
+        }
+        let mut listing = fs::read_dir(&self.dir)
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .collect::<Vec<PathBuf>>()
+            })
+            .unwrap_or_default();
+        listing.sort();
+        for path in listing.iter().filter(|path| path.is_dir()) {
+            entries.push(BrowserEntry::new(BrowserEntryKind::Directory, path.clone()));
+        }
+        for path in listing.iter().filter(|path| RomBrowser::is_rom(path)) {
+            entries.push(BrowserEntry::new(BrowserEntryKind::Rom, path.clone()));
+        }
+        self.entries = entries;
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    //Looks up to count ROMs not looked up yet, the selected one first
+    pub fn look_up(&mut self, database: &RomDatabase, count: usize) {
+        let selected = self.selected.min(self.entries.len());
+        let (before, after) = self.entries.split_at_mut(selected);
+        after
+            .iter_mut()
+            .chain(before.iter_mut())
+            .filter(|entry| {
+                matches!(entry.kind, BrowserEntryKind::Recent | BrowserEntryKind::Rom)
+                    && !entry.looked_up
+            })
+            .take(count)
+            .for_each(|entry| entry.look_up(database));
+    }
+
+    //Files with a ROM extension or none at all, many ROMs are distributed as plain names like PONG
+    pub fn is_rom(path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        path.is_file()
+            && !name.starts_with('.')
+            && match path.extension() {
+                Some(ext) => {
+                    RomBrowser::EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+                }
+                None => true,
+            }
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    //Directories are entered, the path of a ROM is returned for loading
+    pub fn select(&mut self, recent: &RecentRoms) -> Option<PathBuf> {
+        let entry = self.entries.get(self.selected)?.clone();
+        match entry.kind {
+            BrowserEntryKind::Recent | BrowserEntryKind::Rom => Some(entry.path),
+            BrowserEntryKind::Parent => {
+                self.parent(recent);
+                None
+            }
+            BrowserEntryKind::Directory => {
+                self.dir = entry.path;
+                self.selected = 0;
+                self.refresh(recent);
+                None
+            }
+        }
+    }
+
+    pub fn parent(&mut self, recent: &RecentRoms) {
+        let dir = fs::canonicalize(&self.dir).unwrap_or_else(|_| self.dir.clone());
+        if let Some(parent) = dir.parent() {
+            self.dir = parent.to_path_buf();
+            self.selected = 0;
+            self.refresh(recent);
+        }
+    }
+}
+
+impl RecentRoms {
+    const FILE: &'static str = "recent-roms.txt";
+    const MAX: usize = 10;
+
+    //None when the system has no config directory, the list is then kept for the session only
+    pub fn file() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8").join(RecentRoms::FILE))
+    }
+
+    pub fn load_file(filename: &Path) -> RecentRoms {
+        let paths = fs::read_to_string(filename)
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .take(RecentRoms::MAX)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        RecentRoms { paths }
+    }
+
+    //Moves filename to the front, stdin has no path worth remembering
+    pub fn add(&mut self, filename: &str) {
+        if filename == "-" {
+            return;
+        }
+        self.paths.retain(|path| path != filename);
+        self.paths.insert(0, filename.to_string());
+        self.paths.truncate(RecentRoms::MAX);
+    }
+
+    pub fn save_file(&self, filename: &Path) -> io::Result<()> {
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(filename, self.paths.join("\n") + "\n")
+    }
+}
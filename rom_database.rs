@@ -0,0 +1,245 @@
+// ROM metadata in the format of the community CHIP-8 database (programs.json), a list of programs:
+//
+//   {
+//     "title": "...", "authors": ["..."],
+//     "roms": {
+//       "<sha1 of the ROM>": {
+//         "platforms": ["originalChip8", ...],  in order of preference
+//         "tickrate": 15,                       instructions per frame
+//         "keys": { "up": 5, "a": 6 },          up/down/left/right go to the arrow keys, a to space and b to enter
+//         "quirkyPlatforms": { "originalChip8": { "shift": true } }
+//       }
+//     }
+//   }
+//
+// data/programs.json is built into the binary, entries in a local rom-database.json are added on top of it.
+use crate::settings::{QuirkSettings, RomSettings};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseQuirks {
+    // 8xy6/8xyE shift Vx in place
+    pub shift: Option<bool>,
+    // Fx55/Fx65 leave I unchanged
+    #[serde(rename = "memoryLeaveIUnchanged")]
+    pub memory_leave_i_unchanged: Option<bool>,
+    // Sprites wrap around the edge of the screen
+    pub wrap: Option<bool>,
+    // Bnnn jumps to nnn + Vx
+    pub jump: Option<bool>,
+    // 8xy1/8xy2/8xy3 reset VF
+    pub logic: Option<bool>,
+    // Dxyn waits for vertical blank
+    pub vblank: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseRom {
+    pub platforms: Vec<String>,
+    pub tickrate: Option<u32>,
+    pub keys: HashMap<String, u8>,
+    #[serde(rename = "quirkyPlatforms")]
+    pub quirky_platforms: HashMap<String, DatabaseQuirks>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseProgram {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, DatabaseRom>,
+}
+
+//What the database knows about one ROM
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<String>,
+    pub rom: DatabaseRom,
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl DatabaseQuirks {
+    //Quirks of the platforms a ROM can ask for, platforms we don't know are skipped
+    fn for_platform(platform: &str) -> Option<DatabaseQuirks> {
+        let (shift, memory_leave_i_unchanged, wrap, jump, logic, vblank) = match platform {
+            "originalChip8" | "hybridVIP" => (false, false, false, false, true, true),
+            "modernChip8" => (false, false, false, false, false, false),
+            // These add x to I instead of x + 1, the closest we have is incrementing it
+            "chip48" | "superchip1" => (true, false, false, true, false, false),
+            "superchip" => (true, true, false, true, false, false),
+            "xochip" => (false, false, true, false, false, false),
+            _ => return None,
+        };
+        Some(DatabaseQuirks {
+            shift: Some(shift),
+            memory_leave_i_unchanged: Some(memory_leave_i_unchanged),
+            wrap: Some(wrap),
+            jump: Some(jump),
+            logic: Some(logic),
+            vblank: Some(vblank),
+        })
+    }
+
+    fn merge(&mut self, other: &DatabaseQuirks) {
+        self.shift = other.shift.or(self.shift);
+        self.memory_leave_i_unchanged = other
+            .memory_leave_i_unchanged
+            .or(self.memory_leave_i_unchanged);
+        self.wrap = other.wrap.or(self.wrap);
+        self.jump = other.jump.or(self.jump);
+        self.logic = other.logic.or(self.logic);
+        self.vblank = other.vblank.or(self.vblank);
+    }
+
+    fn to_settings(&self) -> QuirkSettings {
+        QuirkSettings {
+            shift_uses_vy: self.shift.map(|shift| !shift),
+            load_store_increments_i: self.memory_leave_i_unchanged.map(|unchanged| !unchanged),
+            jump_uses_vx: self.jump,
+            logic_resets_vf: self.logic,
+            clip_sprites: self.wrap.map(|wrap| !wrap),
+            display_wait: self.vblank,
+        }
+    }
+}
+
+impl RomInfo {
+    //Same shape as a [roms.*] entry of chip8.toml so it can sit between the defaults and the overrides
+    pub fn to_settings(&self) -> RomSettings {
+        let mut quirks = self
+            .platform
+            .as_deref()
+            .and_then(DatabaseQuirks::for_platform)
+            .unwrap_or_default();
+        if let Some(overrides) = self
+            .platform
+            .as_ref()
+            .and_then(|platform| self.rom.quirky_platforms.get(platform))
+        {
+            quirks.merge(overrides);
+        }
+        let keymap = self
+            .rom
+            .keys
+            .iter()
+            .filter_map(|(name, key)| {
+                let name = match name.as_str() {
+                    "up" | "down" | "left" | "right" => name.as_str(),
+                    "a" => "space",
+                    "b" => "enter",
+                    _ => return None,
+                };
+                Some((name.to_string(), *key))
+            })
+            .collect();
+        RomSettings {
+            name: Some(self.title.clone()),
+            cpu_hz: self.rom.tickrate.map(|tickrate| tickrate * 60),
+            quirks: quirks.to_settings(),
+            keymap,
+            ..RomSettings::default()
+        }
+    }
+}
+
+impl RomDatabase {
+    const BUILT_IN: &'static str = include_str!("data/programs.json");
+    pub const LOCAL_FILE: &'static str = "rom-database.json";
+
+    pub fn new() -> RomDatabase {
+        let mut res = RomDatabase {
+            roms: HashMap::new(),
+        };
+        res.add_programs(serde_json::from_str(RomDatabase::BUILT_IN).unwrap());
+        res
+    }
+
+    //Entries in the file replace built in ones with the same hash, a missing file adds nothing
+    pub fn load_file(&mut self, filename: &Path) -> io::Result<usize> {
+        if !filename.is_file() {
+            return Ok(0);
+        }
+        let programs: Vec<DatabaseProgram> = serde_json::from_str(&fs::read_to_string(filename)?)?;
+        Ok(self.add_programs(programs))
+    }
+
+    fn add_programs(&mut self, programs: Vec<DatabaseProgram>) -> usize {
+        let mut count = 0;
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let platform = rom
+                    .platforms
+                    .iter()
+                    .find(|platform| DatabaseQuirks::for_platform(platform).is_some())
+                    .cloned();
+                self.roms.insert(
+                    hash.to_lowercase(),
+                    RomInfo {
+                        title: program.title.clone(),
+                        authors: program.authors.clone(),
+                        platform,
+                        rom,
+                    },
+                );
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IBM_LOGO: &str = "1ba58656810b67fd131eb9af3e3987863bf26c90";
+
+    #[test]
+    fn built_in_roms_resolve_without_a_local_file() {
+        let mut database = RomDatabase::new();
+        let added = database
+            .load_file(Path::new("no-such-dir/rom-database.json"))
+            .unwrap();
+        assert_eq!(added, 0);
+        let info = database.lookup(IBM_LOGO).unwrap();
+        assert_eq!(info.title, "IBM Logo");
+        assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+        assert_eq!(info.to_settings().quirks.display_wait, Some(true));
+    }
+
+    #[test]
+    fn local_entries_replace_built_in_ones() {
+        let filename = std::env::temp_dir().join(format!("chip8-db-{}.json", std::process::id()));
+        fs::write(
+            &filename,
+            format!(
+                r#"[{{"title": "Local", "roms": {{"{}": {{"platforms": ["modernChip8"]}}}}}}]"#,
+                IBM_LOGO.to_uppercase()
+            ),
+        )
+        .unwrap();
+        let mut database = RomDatabase::new();
+        let added = database.load_file(&filename);
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(added.unwrap(), 1);
+        let info = database.lookup(IBM_LOGO).unwrap();
+        assert_eq!(info.title, "Local");
+        assert_eq!(info.platform.as_deref(), Some("modernChip8"));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::chip8::display::Chip8Display;
+use crate::palette::Palette;
+
+use std::fmt::Write;
+use std::{fs, io, path::Path};
+
+//Pixels of the display scaled up by an integer factor, row by row
+fn scaled_rows(display: &Chip8Display, scale: usize) -> Vec<Vec<bool>> {
+    let mut res = Vec::with_capacity(Chip8Display::HEIGHT * scale);
+    for y in 0..Chip8Display::HEIGHT {
+        let row = (0..Chip8Display::WIDTH * scale)
+            .map(|x| display.get_pixel(x / scale, y))
+            .collect::<Vec<bool>>();
+        for _ in 0..scale {
+            res.push(row.clone());
+        }
+    }
+    res
+}
+
+pub fn to_pbm(display: &Chip8Display, scale: usize) -> String {
+    let rows = scaled_rows(display, scale);
+    let mut res = String::new();
+    writeln!(&mut res, "P1").unwrap();
+    writeln!(
+        &mut res,
+        "{} {}",
+        Chip8Display::WIDTH * scale,
+        Chip8Display::HEIGHT * scale
+    )
+    .unwrap();
+    for row in rows.iter() {
+        let line = row
+            .iter()
+            .map(|bit| if *bit { "1" } else { "0" })
+            .collect::<Vec<&str>>()
+            .join(" ");
+        writeln!(&mut res, "{}", line).unwrap();
+    }
+    res
+}
+
+pub fn to_ascii(display: &Chip8Display, scale: usize) -> String {
+    let rows = scaled_rows(display, scale);
+    let mut res = String::new();
+    for row in rows.iter() {
+        let line = row
+            .iter()
+            .map(|bit| if *bit { '#' } else { '.' })
+            .collect::<String>();
+        writeln!(&mut res, "{}", line).unwrap();
+    }
+    res
+}
+
+pub fn to_png(display: &Chip8Display, scale: usize, palette: &Palette) -> io::Result<Vec<u8>> {
+    let rows = scaled_rows(display, scale);
+    let mut data = Vec::with_capacity(rows.len() * Chip8Display::WIDTH * scale * 3);
+    for row in rows.iter() {
+        for bit in row.iter() {
+            let (r, g, b) = if *bit { palette.on } else { palette.off };
+            data.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut encoder = png::Encoder::new(
+        &mut res,
+        (Chip8Display::WIDTH * scale) as u32,
+        (Chip8Display::HEIGHT * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(res)
+}
+
+//Writes <base>.pbm, <base>.png and <base>.txt
+pub fn save(
+    display: &Chip8Display,
+    scale: usize,
+    palette: &Palette,
+    base: &Path,
+) -> io::Result<()> {
+    if let Some(dir) = base.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let scale = scale.max(1);
+    fs::write(base.with_extension("pbm"), to_pbm(display, scale))?;
+    fs::write(base.with_extension("png"), to_png(display, scale, palette)?)?;
+    fs::write(base.with_extension("txt"), to_ascii(display, scale))?;
+    Ok(())
+}
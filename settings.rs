@@ -0,0 +1,208 @@
+// Settings file (chip8.toml), every key is optional:
+//
+//   [defaults]
+//   tick_rate_ms = 16       how often the screen is redrawn
+//   key_timeout_ms = 250    how long a key stays down after the last key press
+//   cpu_hz = 500            instructions per second
+//   timer_hz = 60           delay/sound timer rate
+//   timing = "vip"          "instructions" (cpu_hz of them a second) or "vip" (COSMAC VIP interpreter cycle costs)
+//   palette = "green"       see Palette::names
+//
+//   [defaults.quirks]       names as in Chip8Quirks
+//   shift_uses_vy = true
+//
+//   [defaults.keymap]       terminal key = chip8 key, keys are a character or up/down/left/right/space/enter/tab
+//   w = 0x1
+//
+//   [roms.<sha1 of the ROM>]
+//   same keys as [defaults], applied on top of them and of what the ROM database or an Octo cartridge says
+use crate::chip8::config::{Chip8Config, Chip8Quirks, Chip8Timing};
+use crate::chip8::octo_cartridge::Chip8OctoOptions;
+
+use crossterm::event::KeyCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirkSettings {
+    pub shift_uses_vy: Option<bool>,
+    pub load_store_increments_i: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub logic_resets_vf: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub display_wait: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomSettings {
+    // Only for the reader of the file, ROMs are matched by hash
+    pub name: Option<String>,
+    pub tick_rate_ms: Option<u64>,
+    pub key_timeout_ms: Option<u64>,
+    pub cpu_hz: Option<u32>,
+    pub timer_hz: Option<u32>,
+    pub timing: Option<Chip8Timing>,
+    pub palette: Option<String>,
+    pub quirks: QuirkSettings,
+    pub keymap: HashMap<String, u8>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub defaults: RomSettings,
+    pub roms: HashMap<String, RomSettings>,
+}
+
+impl QuirkSettings {
+    fn merge(&mut self, other: &QuirkSettings) {
+        self.shift_uses_vy = other.shift_uses_vy.or(self.shift_uses_vy);
+        self.load_store_increments_i = other
+            .load_store_increments_i
+            .or(self.load_store_increments_i);
+        self.jump_uses_vx = other.jump_uses_vx.or(self.jump_uses_vx);
+        self.logic_resets_vf = other.logic_resets_vf.or(self.logic_resets_vf);
+        self.clip_sprites = other.clip_sprites.or(self.clip_sprites);
+        self.display_wait = other.display_wait.or(self.display_wait);
+    }
+
+    pub fn apply(&self, quirks: &mut Chip8Quirks) {
+        quirks.shift_uses_vy = self.shift_uses_vy.unwrap_or(quirks.shift_uses_vy);
+        quirks.load_store_increments_i = self
+            .load_store_increments_i
+            .unwrap_or(quirks.load_store_increments_i);
+        quirks.jump_uses_vx = self.jump_uses_vx.unwrap_or(quirks.jump_uses_vx);
+        quirks.logic_resets_vf = self.logic_resets_vf.unwrap_or(quirks.logic_resets_vf);
+        quirks.clip_sprites = self.clip_sprites.unwrap_or(quirks.clip_sprites);
+        quirks.display_wait = self.display_wait.unwrap_or(quirks.display_wait);
+    }
+}
+
+impl RomSettings {
+    //Values set in other win, key maps are combined
+    pub fn merge(&mut self, other: &RomSettings) {
+        self.name = other.name.clone().or_else(|| self.name.take());
+        self.tick_rate_ms = other.tick_rate_ms.or(self.tick_rate_ms);
+        self.key_timeout_ms = other.key_timeout_ms.or(self.key_timeout_ms);
+        self.cpu_hz = other.cpu_hz.or(self.cpu_hz);
+        self.timer_hz = other.timer_hz.or(self.timer_hz);
+        self.timing = other.timing.or(self.timing);
+        self.palette = other.palette.clone().or_else(|| self.palette.take());
+        self.quirks.merge(&other.quirks);
+        self.keymap
+            .extend(other.keymap.iter().map(|(name, key)| (name.clone(), *key)));
+    }
+
+    //Octo's quirk flags say when a ROM deviates from the original interpreter
+    pub fn from_octo(options: &Chip8OctoOptions) -> RomSettings {
+        RomSettings {
+            cpu_hz: options.tickrate.map(|tickrate| tickrate * 60),
+            quirks: QuirkSettings {
+                shift_uses_vy: options.shift_quirks.map(|quirk| !quirk),
+                load_store_increments_i: options.load_store_quirks.map(|quirk| !quirk),
+                jump_uses_vx: options.jump_quirks,
+                logic_resets_vf: options.logic_quirks,
+                clip_sprites: options.clip_quirks,
+                display_wait: options.v_blank_quirks,
+            },
+            ..RomSettings::default()
+        }
+    }
+
+    pub fn apply(&self, config: &mut Chip8Config) {
+        self.quirks.apply(&mut config.quirks);
+        config.cpu_hz = self.cpu_hz.unwrap_or(config.cpu_hz);
+        config.timer_hz = self.timer_hz.unwrap_or(config.timer_hz);
+        config.timing = self.timing.unwrap_or(config.timing);
+    }
+
+    //Terminal keys to chip8 keys, the arrow keys stay on 0-3 unless the key map says otherwise
+    const KEYPAD_LAYOUT: [&'static str; 4] = ["1234", "qwer", "asdf", "zxcv"];
+    const KEYPAD: [[u8; 4]; 4] = [
+        [0x1, 0x2, 0x3, 0xC],
+        [0x4, 0x5, 0x6, 0xD],
+        [0x7, 0x8, 0x9, 0xE],
+        [0xA, 0x0, 0xB, 0xF],
+    ];
+
+    pub fn key_bindings(&self) -> Result<HashMap<KeyCode, u8>, String> {
+        let mut res = HashMap::from([
+            (KeyCode::Down, 0),
+            (KeyCode::Up, 1),
+            (KeyCode::Right, 2),
+            (KeyCode::Left, 3),
+        ]);
+        //The usual layout of the hex keypad on the left of a QWERTY keyboard
+        for (row, keys) in RomSettings::KEYPAD_LAYOUT.iter().enumerate() {
+            for (column, c) in keys.chars().enumerate() {
+                res.insert(KeyCode::Char(c), RomSettings::KEYPAD[row][column]);
+            }
+        }
+        for (name, key) in self.keymap.iter() {
+            if *key > 0xF {
+                return Err(format!(
+                    "Key {} is mapped to {:#X}, keys go up to 0xF",
+                    name, key
+                ));
+            }
+            res.insert(Settings::parse_key(name)?, *key);
+        }
+        Ok(res)
+    }
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings::default()
+    }
+
+    //A missing file is the same as an empty one
+    pub fn load_file(filename: &Path) -> io::Result<Settings> {
+        if !filename.is_file() {
+            return Ok(Settings::new());
+        }
+        toml::from_str(&fs::read_to_string(filename)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.message().to_string()))
+    }
+
+    //Lowercase hex SHA-1 of the ROM contents, the key used in [roms.*]
+    pub fn rom_hash(contents: &[u8]) -> String {
+        sha1_smol::Sha1::from(contents).digest().to_string()
+    }
+
+    //Defaults, then what is known about the ROM in order (database entry, cartridge options), then the overrides for the ROM
+    pub fn for_rom(&self, hash: &str, known: &[RomSettings]) -> RomSettings {
+        let mut res = self.defaults.clone();
+        for settings in known.iter() {
+            res.merge(settings);
+        }
+        if let Some(rom) = self.roms.get(hash) {
+            res.merge(rom);
+        }
+        res
+    }
+
+    fn parse_key(name: &str) -> Result<KeyCode, String> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => return Ok(KeyCode::Char(c)),
+            (None, _) => return Err("Empty key name in key map".to_string()),
+            _ => {}
+        }
+        match name.to_lowercase().as_str() {
+            "up" => Ok(KeyCode::Up),
+            "down" => Ok(KeyCode::Down),
+            "left" => Ok(KeyCode::Left),
+            "right" => Ok(KeyCode::Right),
+            "space" => Ok(KeyCode::Char(' ')),
+            "enter" => Ok(KeyCode::Enter),
+            "tab" => Ok(KeyCode::Tab),
+            _ => Err(format!("Unknown key {} in key map", name)),
+        }
+    }
+}
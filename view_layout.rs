@@ -0,0 +1,148 @@
+use crate::renderer::RenderMode;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayoutKind {
+    // Screen on the left, debug info on the right
+    SideBySide,
+
+    // Debug info on the left taking most of the width, a smaller screen on the right
+    Debugger,
+
+    // Screen above the debug info, for narrow terminals
+    Stacked,
+}
+
+//How the screen and the debug info share the terminal, written to layout.toml whenever it
+//changes so the next session starts the same way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewLayout {
+    pub kind: LayoutKind,
+
+    // Only the screen, centred
+    pub fullscreen: bool,
+
+    // Share of the terminal given to the screen in percent, one for each kind
+    pub side_by_side: u16,
+    pub debugger: u16,
+    pub stacked: u16,
+}
+
+impl LayoutKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            LayoutKind::SideBySide => "side by side",
+            LayoutKind::Debugger => "debugger",
+            LayoutKind::Stacked => "stacked",
+        }
+    }
+
+    pub fn next(self) -> LayoutKind {
+        match self {
+            LayoutKind::SideBySide => LayoutKind::Debugger,
+            LayoutKind::Debugger => LayoutKind::Stacked,
+            LayoutKind::Stacked => LayoutKind::SideBySide,
+        }
+    }
+}
+
+impl Default for ViewLayout {
+    fn default() -> ViewLayout {
+        ViewLayout {
+            kind: LayoutKind::SideBySide,
+            fullscreen: false,
+            side_by_side: 60,
+            debugger: 35,
+            stacked: 60,
+        }
+    }
+}
+
+impl ViewLayout {
+    pub const FILE: &'static str = "layout.toml";
+    const MIN_SIZE: u16 = 20;
+    const MAX_SIZE: u16 = 80;
+
+    //A missing or unreadable file gives the default layout
+    pub fn load_file(filename: &Path) -> ViewLayout {
+        fs::read_to_string(filename)
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save_file(&self, filename: &Path) -> io::Result<()> {
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(filename, contents)
+    }
+
+    //Share of the screen for the current kind
+    pub fn size(&self) -> u16 {
+        match self.kind {
+            LayoutKind::SideBySide => self.side_by_side,
+            LayoutKind::Debugger => self.debugger,
+            LayoutKind::Stacked => self.stacked,
+        }
+    }
+
+    //Grows or shrinks the screen of the current kind, kept between MIN_SIZE and MAX_SIZE percent
+    pub fn resize(&mut self, delta: i16) {
+        let size = (self.size() as i16 + delta)
+            .clamp(ViewLayout::MIN_SIZE as i16, ViewLayout::MAX_SIZE as i16)
+            as u16;
+        match self.kind {
+            LayoutKind::SideBySide => self.side_by_side = size,
+            LayoutKind::Debugger => self.debugger = size,
+            LayoutKind::Stacked => self.stacked = size,
+        }
+    }
+
+    //Area for the screen block and for the debug info, which is hidden in fullscreen
+    pub fn split(&self, area: Rect) -> (Rect, Option<Rect>) {
+        if self.fullscreen {
+            let screen = RenderMode::fit(area);
+            let x = area.x + area.width.saturating_sub(screen.width) / 2;
+            let y = area.y + area.height.saturating_sub(screen.height) / 2;
+            return (Rect::new(x, y, screen.width, screen.height), None);
+        }
+        let size = self.size().min(100);
+        let (direction, constraints) = match self.kind {
+            LayoutKind::SideBySide => (
+                Direction::Horizontal,
+                [
+                    Constraint::Percentage(size),
+                    Constraint::Percentage(100 - size),
+                ],
+            ),
+            LayoutKind::Debugger => (
+                Direction::Horizontal,
+                [
+                    Constraint::Percentage(100 - size),
+                    Constraint::Percentage(size),
+                ],
+            ),
+            LayoutKind::Stacked => (
+                Direction::Vertical,
+                [
+                    Constraint::Percentage(size),
+                    Constraint::Percentage(100 - size),
+                ],
+            ),
+        };
+        let chunks = Layout::default()
+            .direction(direction)
+            .constraints(constraints.as_ref())
+            .split(area);
+        match self.kind {
+            LayoutKind::Debugger => (RenderMode::fit(chunks[1]), Some(chunks[0])),
+            _ => (RenderMode::fit(chunks[0]), Some(chunks[1])),
+        }
+    }
+}